@@ -0,0 +1,117 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use jsonrpc_core::serde_json::{self, Value};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// One upstream JSON-RPC request and its result.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Exchange {
+    pub method: String,
+    pub params: Value,
+    pub result: Value,
+    /// The request failed, `result` is null then.
+    #[serde(default)]
+    pub failed: bool,
+}
+
+/// The upstream traffic of one server request, enough to run it again without a node.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Bundle {
+    /// The server method and params the traffic was recorded for.
+    pub method: String,
+    pub request: Value,
+    pub exchanges: Vec<Exchange>,
+}
+
+impl Bundle {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+}
+
+/// Directory recorded bundles are saved to as `<id>.json`.
+pub struct BundleStore {
+    dir: PathBuf,
+    next: AtomicU64,
+}
+
+impl BundleStore {
+    pub fn new(dir: PathBuf) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            next: AtomicU64::new(0),
+        })
+    }
+
+    /// Saves the bundle under a new id, unique as long as the clock doesn't go backwards.
+    pub fn save(&self, bundle: &Bundle) -> Result<String, Error> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|v| v.as_millis())
+            .unwrap_or_default();
+        let id = format!("{millis}-{}", self.next.fetch_add(1, Ordering::Relaxed));
+
+        let json = serde_json::to_vec_pretty(bundle).expect("bundles serialize to JSON");
+        std::fs::write(self.path(&id), json)
+            .map_err(|_| Error::InvalidRequest("Failed to save bundle"))?;
+        Ok(id)
+    }
+
+    pub fn get(&self, id: &str) -> Result<Bundle, Error> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit() || c == '-') {
+            return Err(Error::InvalidRequest("Invalid bundle id"));
+        }
+        Bundle::load(&self.path(id)).map_err(|_| Error::InvalidRequest("Bundle not found"))
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.json"))
+    }
+}
+
+/// Answers upstream requests from a bundle instead of the node.
+pub struct Replay {
+    exchanges: Vec<Exchange>,
+    used: Mutex<Vec<bool>>,
+}
+
+impl Replay {
+    pub fn new(bundle: Bundle) -> Self {
+        Self {
+            used: Mutex::new(vec![false; bundle.exchanges.len()]),
+            exchanges: bundle.exchanges,
+        }
+    }
+
+    /// Serves recorded results in order: the first unused exchange with the same method and
+    /// params, or the last one once all of them were served, e.g. when a request is replayed
+    /// several times.
+    pub fn answer(&self, method: &str, params: &Value) -> Result<Value, Error> {
+        let mut used = self.used.lock().unwrap();
+        let matching: Vec<usize> = (0..self.exchanges.len())
+            .filter(|&i| self.exchanges[i].method == method && &self.exchanges[i].params == params)
+            .collect();
+        let index = matching
+            .iter()
+            .find(|&&i| !used[i])
+            .or(matching.last())
+            .copied()
+            .ok_or(Error::InvalidRequest(
+                "Request not recorded in the replayed bundle",
+            ))?;
+        used[index] = true;
+
+        let exchange = &self.exchanges[index];
+        if exchange.failed {
+            return Err(Error::JsonRpcRequestError);
+        }
+        Ok(exchange.result.clone())
+    }
+}
@@ -0,0 +1,236 @@
+use ckb_hash::blake2b_256;
+use ckb_jsonrpc_types::{CellOutput, OutPoint, Script, ScriptHashType};
+use ckb_sdk::constants::TYPE_ID_CODE_HASH;
+use ckb_sdk::rpc::ckb_indexer::{Cell, Order, SearchKey, SearchMode};
+use ckb_sdk::traits::{CellQueryOptions, ValueRangeOption};
+use ckb_types::{packed, prelude::*, H256};
+use ckb_vm::Bytes;
+
+use crate::error::Error;
+use crate::resolver::get_transaction;
+use crate::rpc_client::RpcClient;
+use crate::types::CodeLocator;
+
+/// Page size used when scanning type ID cells for a code hash.
+const CODE_SEARCH_PAGE_SIZE: u32 = 20;
+/// Upper bound of type ID cells scanned before giving up on a type hash.
+const CODE_SEARCH_MAX_CELLS: usize = 1000;
+/// Upper bound of type ID cells scanned before giving up on a data hash. Each cell whose data
+/// hash isn't cached yet is downloaded, so this is much lower.
+const DATA_SEARCH_MAX_CELLS: usize = 100;
+
+/// A resolved code cell and its binary. Binaries supplied inline by `run_binary` have no cell.
+pub struct CodeCell {
+    pub out_point: Option<OutPoint>,
+    pub output: Option<CellOutput>,
+    pub data: Bytes,
+    /// False when the cell has been spent and was loaded from the transaction creating it.
+    pub live: bool,
+}
+
+impl CodeCell {
+    pub fn from_binary(data: Bytes) -> Self {
+        Self {
+            out_point: None,
+            output: None,
+            data,
+            live: true,
+        }
+    }
+
+    pub fn data_hash(&self) -> H256 {
+        H256(blake2b_256(&self.data))
+    }
+
+    pub fn type_hash(&self) -> Option<H256> {
+        self.output
+            .as_ref()
+            .and_then(|output| output.type_.clone())
+            .map(|type_| packed::Script::from(type_).calc_script_hash().unpack())
+    }
+
+    /// Where the code comes from, for logs and errors.
+    pub fn describe(&self) -> String {
+        match &self.out_point {
+            Some(out_point) => format!("code cell {}:{}", out_point.tx_hash, out_point.index),
+            None => "inline binary".to_string(),
+        }
+    }
+
+    /// Whether `script` points at this cell's code according to its `hash_type`.
+    pub fn is_referenced_by(&self, script: &Script) -> bool {
+        match script.hash_type {
+            ScriptHashType::Type => self.type_hash().as_ref() == Some(&script.code_hash),
+            _ => self.data_hash() == script.code_hash,
+        }
+    }
+}
+
+/// Indexer searches only consider cells created up to block `tip` when given.
+pub async fn load_code(
+    rpc: &RpcClient,
+    locator: &CodeLocator,
+    tip: Option<u64>,
+) -> Result<CodeCell, Error> {
+    match locator {
+        CodeLocator::OutPoint(out_point) => {
+            let code = load_live_code(rpc, out_point.clone()).await?;
+            match code {
+                Some(code) => Ok(code),
+                None => load_dead_code(rpc, out_point.clone()).await,
+            }
+        }
+        CodeLocator::TypeScript { type_script } => {
+            let mut query = CellQueryOptions::new_type(type_script.clone().into());
+            query.script_search_mode = Some(SearchMode::Exact);
+            query.with_data = Some(true);
+            query.block_range = tip.map(|tip| ValueRangeOption::new(0, tip + 1));
+            let cell = rpc
+                .get_cells(query.into(), Order::Asc, 1, None)
+                .await?
+                .objects
+                .into_iter()
+                .next()
+                .ok_or(Error::InvalidRequest("Code cell not found"))?;
+            code_from_cell(cell)
+        }
+        CodeLocator::CodeHash {
+            code_hash,
+            hash_type,
+        } => search_code_hash(rpc, code_hash, hash_type.clone(), tip).await,
+    }
+}
+
+/// Looks up the live code cell matching `code_hash` among cells deployed behind
+/// a type ID, comparing type script hashes for the `type` hash type and data
+/// hashes otherwise. The indexer can't search by data hash, so code that isn't
+/// behind a type ID is never found this way.
+///
+/// Scans the newest cells first, as upgraded code is, and fails with
+/// [`Error::CodeSearchLimit`] when the code isn't among the first
+/// `CODE_SEARCH_MAX_CELLS` cells, or `DATA_SEARCH_MAX_CELLS` for data hashes.
+/// Data hashes are cached by out point in the client, so a cell's code is only
+/// downloaded by the first search reaching it.
+async fn search_code_hash(
+    rpc: &RpcClient,
+    code_hash: &H256,
+    hash_type: ScriptHashType,
+    tip: Option<u64>,
+) -> Result<CodeCell, Error> {
+    let type_id = Script {
+        code_hash: TYPE_ID_CODE_HASH,
+        hash_type: ScriptHashType::Type,
+        args: Default::default(),
+    };
+    let mut query = CellQueryOptions::new_type(type_id.into());
+    query.script_search_mode = Some(SearchMode::Prefix);
+    query.with_data = Some(false);
+    query.block_range = tip.map(|tip| ValueRangeOption::new(0, tip + 1));
+    let search_key = SearchKey::from(query);
+    let max_cells = match hash_type {
+        ScriptHashType::Type => CODE_SEARCH_MAX_CELLS,
+        _ => DATA_SEARCH_MAX_CELLS,
+    };
+
+    let mut cursor = None;
+    for _ in 0..max_cells.div_ceil(CODE_SEARCH_PAGE_SIZE as usize) {
+        let page = rpc
+            .get_cells(
+                search_key.clone(),
+                Order::Desc,
+                CODE_SEARCH_PAGE_SIZE,
+                cursor,
+            )
+            .await?;
+        if page.objects.is_empty() {
+            return Err(Error::InvalidRequest("Code cell not found"));
+        }
+
+        for cell in page.objects {
+            if hash_type == ScriptHashType::Type {
+                let type_hash = cell
+                    .output
+                    .type_
+                    .map(|type_| packed::Script::from(type_).calc_script_hash().unpack());
+                if type_hash.as_ref() == Some(code_hash) {
+                    return load_live_code(rpc, cell.out_point)
+                        .await?
+                        .ok_or(Error::InvalidRequest("Cell not found"));
+                }
+                continue;
+            }
+
+            if let Some(data_hash) = rpc.cached_data_hash(&cell.out_point) {
+                if &data_hash != code_hash {
+                    continue;
+                }
+            }
+            // spent since the scan
+            let Some(code) = load_live_code(rpc, cell.out_point.clone()).await? else {
+                continue;
+            };
+            let data_hash = code.data_hash();
+            rpc.cache_data_hash(cell.out_point, data_hash.clone());
+            if &data_hash == code_hash {
+                return Ok(code);
+            }
+        }
+        cursor = Some(page.last_cursor);
+    }
+
+    Err(Error::CodeSearchLimit(max_cells))
+}
+
+async fn load_live_code(rpc: &RpcClient, out_point: OutPoint) -> Result<Option<CodeCell>, Error> {
+    let Some(cell) = rpc.get_live_cell(&out_point, true).await?.cell else {
+        return Ok(None);
+    };
+    let data = cell
+        .data
+        .ok_or(Error::InvalidRequest("Cell doesn't have data"))?
+        .content
+        .into_bytes();
+    Ok(Some(CodeCell {
+        out_point: Some(out_point),
+        output: Some(cell.output),
+        data,
+        live: true,
+    }))
+}
+
+/// Loads the code of a spent cell from the transaction that created it.
+async fn load_dead_code(rpc: &RpcClient, out_point: OutPoint) -> Result<CodeCell, Error> {
+    let tx = get_transaction(rpc, &out_point.tx_hash).await?.inner;
+    let index = u32::from(out_point.index) as usize;
+    let output = tx
+        .outputs
+        .get(index)
+        .ok_or(Error::InvalidRequest("Cell not found"))?
+        .clone();
+    let data = tx
+        .outputs_data
+        .get(index)
+        .ok_or(Error::InvalidRequest("Cell not found"))?
+        .clone()
+        .into_bytes();
+
+    Ok(CodeCell {
+        out_point: Some(out_point),
+        output: Some(output),
+        data,
+        live: false,
+    })
+}
+
+fn code_from_cell(cell: Cell) -> Result<CodeCell, Error> {
+    let data = cell
+        .output_data
+        .ok_or(Error::InvalidRequest("Cell doesn't have data"))?
+        .into_bytes();
+    Ok(CodeCell {
+        out_point: Some(cell.out_point),
+        output: Some(cell.output),
+        data,
+        live: true,
+    })
+}
@@ -0,0 +1,94 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use crate::ssri_vm::{Limits, VmBackend};
+
+/// Command line options of the server.
+#[derive(Parser, Clone)]
+#[command(version)]
+pub struct Config {
+    /// CKB node RPC endpoint, which must also serve the indexer RPC.
+    #[arg(default_value = "https://testnet.ckbapp.dev/")]
+    pub ckb_rpc: String,
+    /// Address the JSON-RPC server listens on.
+    #[arg(default_value = "0.0.0.0:9090")]
+    pub server_addr: String,
+    /// Enables `run_binary`, which executes RISC-V binaries supplied by the caller. Meant for
+    /// development only.
+    #[arg(long)]
+    pub enable_run_binary: bool,
+    /// Directory `run_binary` may load binaries from by path.
+    #[arg(long)]
+    pub binary_dir: Option<PathBuf>,
+    /// Answers all chain lookups from a JSON fixture instead of the CKB node.
+    #[arg(long, value_name = "FIXTURE")]
+    pub mock_chain: Option<PathBuf>,
+    /// Machine running scripts. Profiled, covered and debugged calls always run on the
+    /// interpreter.
+    #[arg(long, value_enum, default_value_t)]
+    pub vm_backend: VmBackend,
+    /// Maximum number of syscalls kept in the trace of an execution.
+    #[arg(long, default_value_t = 1000)]
+    pub trace_limit: usize,
+    /// Lets calls with `debug` wait for GDB on this address, e.g. `127.0.0.1:9999`. Meant for
+    /// development only.
+    #[arg(long, value_name = "ADDR")]
+    pub gdb_listen: Option<String>,
+    /// Largest code ELF the server runs, in bytes.
+    #[arg(long, value_name = "BYTES", default_value_t = 1 << 20)]
+    pub max_code_size: usize,
+    /// Memory of the VM, in bytes: a multiple of 256 KiB up to ckb-vm's 4 MiB.
+    #[arg(long, value_name = "BYTES", default_value_t = ckb_vm::RISCV_MAX_MEMORY, value_parser = parse_vm_memory)]
+    pub vm_memory: usize,
+    /// Largest content a script may set, in bytes.
+    #[arg(long, value_name = "BYTES", default_value_t = 1 << 20)]
+    pub max_content_size: usize,
+    /// Most `debug` output a script may print per execution, in bytes.
+    #[arg(long, value_name = "BYTES", default_value_t = 1 << 16)]
+    pub max_debug_size: usize,
+    /// Records the upstream traffic of every execution into a bundle file in this directory.
+    #[arg(long, value_name = "DIR")]
+    pub record: Option<PathBuf>,
+    /// Answers all chain lookups from a recorded bundle instead of the CKB node.
+    #[arg(long, value_name = "BUNDLE", conflicts_with = "mock_chain")]
+    pub replay: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn limits(&self) -> Limits {
+        Limits {
+            code_size: self.max_code_size,
+            memory: self.vm_memory,
+            content_size: self.max_content_size,
+            debug_size: self.max_debug_size,
+        }
+    }
+}
+
+fn parse_vm_memory(value: &str) -> Result<usize, String> {
+    let memory: usize = value.parse().map_err(|err| format!("{err}"))?;
+    // ckb-vm allocates memory in frames
+    let frame = 1 << ckb_vm::MEMORY_FRAME_SHIFTS;
+    if memory == 0 || !memory.is_multiple_of(frame) || memory > ckb_vm::RISCV_MAX_MEMORY {
+        return Err(format!(
+            "must be a multiple of {frame} up to {}",
+            ckb_vm::RISCV_MAX_MEMORY
+        ));
+    }
+    Ok(memory)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vm_memory_is_whole_frames_up_to_the_maximum() {
+        assert_eq!(parse_vm_memory("262144"), Ok(1 << 18));
+        assert_eq!(parse_vm_memory("4194304"), Ok(ckb_vm::RISCV_MAX_MEMORY));
+        for invalid in ["0", "4096", "8388608", "-1", "1MiB"] {
+            assert!(parse_vm_memory(invalid).is_err(), "{invalid}");
+        }
+    }
+}
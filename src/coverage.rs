@@ -0,0 +1,76 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use ckb_types::H256;
+
+use crate::symbols::Symbols;
+
+/// Hits of the source lines of each file.
+type Files = BTreeMap<String, BTreeMap<u32, u64>>;
+
+/// Hits of the source lines of executed code, per code data hash and file, so binaries built
+/// from files with the same paths are kept apart.
+#[derive(Default)]
+pub struct Coverage {
+    codes: BTreeMap<H256, Files>,
+}
+
+impl Coverage {
+    /// Maps how many times each pc was executed to the source lines of the code's DWARF info.
+    /// Lines without executed instructions are kept with no hits, so they count as missed.
+    pub fn new(code_hash: H256, symbols: &Symbols, executed: &HashMap<u64, u64>) -> Self {
+        let mut files = Files::new();
+        for (address, len, file, line) in symbols.lines() {
+            // instructions are at least 2 bytes long
+            let hits = (address..address + len)
+                .step_by(2)
+                .filter_map(|pc| executed.get(&pc))
+                .max()
+                .copied()
+                .unwrap_or_default();
+            let lines = files.entry(file.to_string()).or_default();
+            let line = lines.entry(line).or_default();
+            *line = (*line).max(hits);
+        }
+        Self {
+            codes: BTreeMap::from([(code_hash, files)]),
+        }
+    }
+
+    /// Adds the hits of `other`, e.g. to aggregate the coverage of several executions.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (code_hash, files) in &other.codes {
+            let merged_files = self.codes.entry(code_hash.clone()).or_default();
+            for (file, lines) in files {
+                let merged = merged_files.entry(file.clone()).or_default();
+                for (line, hits) in lines {
+                    *merged.entry(*line).or_default() += hits;
+                }
+            }
+        }
+    }
+
+    /// The coverage as an lcov tracefile, with the records of each code under a test named
+    /// after its data hash.
+    pub fn lcov(&self) -> String {
+        let mut lcov = String::new();
+        for (code_hash, files) in &self.codes {
+            writeln!(lcov, "TN:{code_hash:#x}").unwrap();
+            Self::records(&mut lcov, files);
+        }
+        lcov
+    }
+
+    fn records(lcov: &mut String, files: &Files) {
+        for (file, lines) in files {
+            writeln!(lcov, "SF:{file}").unwrap();
+            for (line, hits) in lines {
+                writeln!(lcov, "DA:{line},{hits}").unwrap();
+            }
+            let hit = lines.values().filter(|&&hits| hits > 0).count();
+            writeln!(lcov, "LF:{}", lines.len()).unwrap();
+            writeln!(lcov, "LH:{hit}").unwrap();
+            writeln!(lcov, "end_of_record").unwrap();
+        }
+    }
+}
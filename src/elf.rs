@@ -0,0 +1,239 @@
+use addr2line::object::elf::{
+    FileHeader64, Tag_File, ELFCLASS64, ELFDATA2LSB, EM_RISCV, ET_DYN, ET_EXEC, PF_X, PT_LOAD,
+    SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE,
+};
+use addr2line::object::read::elf::{AttributesSection, ElfFile64, FileHeader, ProgramHeader};
+use addr2line::object::{self, LittleEndian, Object, ObjectSection, ObjectSymbol, SectionFlags};
+use ckb_jsonrpc_types::Uint64;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// `Tag_RISCV_arch` of the RISC-V attributes, the ISA string the code was built for.
+const TAG_RISCV_ARCH: u64 = 5;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Section {
+    pub name: String,
+    pub address: Uint64,
+    pub size: Uint64,
+    /// `W`, `A` and `X` for writable, allocated and executable sections, as readelf lists them.
+    pub flags: String,
+}
+
+/// What the header, sections and attributes of a code ELF tell about it.
+pub struct ElfInfo {
+    pub entry_point: u64,
+    pub sections: Vec<Section>,
+    pub has_symbols: bool,
+    /// The ISA extensions the code was built for, `None` when the ELF doesn't record them.
+    pub extensions: Option<Vec<String>>,
+}
+
+/// Checks that `data` is an executable RISC-V 64 ELF ckb-vm can load: little-endian, with
+/// loadable segments and its entry point in an executable one.
+pub fn validate(data: &[u8]) -> Result<ElfFile64<'_, LittleEndian>, Error> {
+    if !data.starts_with(b"\x7fELF") {
+        return Err(Error::InvalidElf("not an ELF".to_string()));
+    }
+    if data.get(4) != Some(&ELFCLASS64) {
+        return Err(Error::InvalidElf("not a 64-bit ELF".to_string()));
+    }
+    if data.get(5) != Some(&ELFDATA2LSB) {
+        return Err(Error::InvalidElf("not a little-endian ELF".to_string()));
+    }
+    let file =
+        ElfFile64::<LittleEndian>::parse(data).map_err(|err| Error::InvalidElf(err.to_string()))?;
+
+    let endian = file.endian();
+    let header = file.raw_header();
+    let machine = header.e_machine(endian);
+    if machine != EM_RISCV {
+        return Err(Error::InvalidElf(format!(
+            "not a RISC-V ELF, machine is {machine}"
+        )));
+    }
+    if !matches!(header.e_type(endian), ET_EXEC | ET_DYN) {
+        return Err(Error::InvalidElf("not an executable ELF".to_string()));
+    }
+
+    let loads: Vec<_> = file
+        .raw_segments()
+        .iter()
+        .filter(|segment| segment.p_type(endian) == PT_LOAD)
+        .collect();
+    if loads.is_empty() {
+        return Err(Error::InvalidElf("no loadable segments".to_string()));
+    }
+    let entry = header.e_entry(endian);
+    let executes_entry = loads.iter().any(|segment| {
+        let start = segment.p_vaddr(endian);
+        segment.p_flags(endian) & PF_X != 0
+            && start <= entry
+            && entry - start < segment.p_memsz(endian)
+    });
+    if !executes_entry {
+        return Err(Error::InvalidElf(format!(
+            "entry point {entry:#x} is outside the executable segments"
+        )));
+    }
+    Ok(file)
+}
+
+/// Validates `data` like [`validate`] and describes it.
+pub fn describe(data: &[u8]) -> Result<ElfInfo, Error> {
+    let file = validate(data)?;
+    let endian = file.endian();
+
+    let sections = file
+        .sections()
+        .filter(|section| section.index().0 != 0)
+        .map(|section| {
+            let sh_flags = match section.flags() {
+                SectionFlags::Elf { sh_flags } => sh_flags,
+                _ => 0,
+            };
+            let flags = [(SHF_WRITE, 'W'), (SHF_ALLOC, 'A'), (SHF_EXECINSTR, 'X')]
+                .into_iter()
+                .filter(|(flag, _)| sh_flags & u64::from(*flag) != 0)
+                .map(|(_, letter)| letter)
+                .collect();
+            Section {
+                name: section.name().unwrap_or_default().to_string(),
+                address: section.address().into(),
+                size: section.size().into(),
+                flags,
+            }
+        })
+        .collect();
+
+    let has_symbols = file
+        .symbols()
+        .any(|symbol| symbol.name().is_ok_and(|name| !name.is_empty()));
+
+    let extensions = file
+        .section_by_name(".riscv.attributes")
+        .and_then(|section| {
+            let attributes = AttributesSection::new(endian, section.data().ok()?).ok()?;
+            riscv_arch(attributes).ok().flatten()
+        })
+        .map(|arch| extensions(&arch));
+
+    Ok(ElfInfo {
+        entry_point: file.raw_header().e_entry(endian),
+        sections,
+        has_symbols,
+        extensions,
+    })
+}
+
+/// The ISA string of the `riscv` attributes, e.g. `rv64i2p1_m2p0_c2p0`.
+fn riscv_arch(
+    attributes: AttributesSection<'_, FileHeader64<LittleEndian>>,
+) -> object::Result<Option<String>> {
+    let mut subsections = attributes.subsections()?;
+    while let Some(subsection) = subsections.next()? {
+        if subsection.vendor() != b"riscv" {
+            continue;
+        }
+        let mut subsubsections = subsection.subsubsections();
+        while let Some(subsubsection) = subsubsections.next()? {
+            if subsubsection.tag() != Tag_File {
+                continue;
+            }
+            let mut reader = subsubsection.attributes();
+            while let Some(tag) = reader.read_tag()? {
+                // odd tags hold strings, even ones integers
+                if tag == TAG_RISCV_ARCH {
+                    let arch = reader.read_string()?;
+                    return Ok(Some(String::from_utf8_lossy(arch).into_owned()));
+                } else if tag % 2 == 1 {
+                    reader.read_string()?;
+                } else {
+                    reader.read_integer()?;
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// The extension names of an ISA string, without versions: `rv64i2p1_m2p0_zba1p0` has `i`,
+/// `m` and `zba`.
+fn extensions(arch: &str) -> Vec<String> {
+    let arch = arch.to_lowercase();
+    let arch = arch.strip_prefix("rv64").unwrap_or(&arch);
+    arch.split('_')
+        .filter_map(|extension| {
+            // versions are `<major>` or `<major>p<minor>`
+            let digits = |c: char| c.is_ascii_digit();
+            let name = extension.trim_end_matches(digits);
+            let name = match name.strip_suffix('p') {
+                Some(major) if major.ends_with(digits) => major.trim_end_matches(digits),
+                _ => name,
+            };
+            (!name.is_empty()).then(|| name.to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn echo() -> Vec<u8> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/vm/echo");
+        std::fs::read(path).unwrap()
+    }
+
+    fn invalid(data: &[u8]) -> String {
+        match validate(data) {
+            Err(Error::InvalidElf(reason)) => reason,
+            Err(err) => panic!("unexpected error {err}"),
+            Ok(_) => panic!("accepted an invalid ELF"),
+        }
+    }
+
+    #[test]
+    fn describes_fixture() {
+        let info = describe(&echo()).unwrap();
+        assert_eq!(info.entry_point, 0x11158);
+        assert!(info.has_symbols);
+        let text = info.sections.iter().find(|v| v.name == ".text").unwrap();
+        assert_eq!(text.flags, "AX");
+        // built without `.riscv.attributes`
+        assert_eq!(info.extensions, None);
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        assert_eq!(invalid(b"MZ"), "not an ELF");
+
+        let mut class32 = echo();
+        class32[4] = 1;
+        assert_eq!(invalid(&class32), "not a 64-bit ELF");
+
+        let mut x86 = echo();
+        x86[18..20].copy_from_slice(&62u16.to_le_bytes());
+        assert_eq!(invalid(&x86), "not a RISC-V ELF, machine is 62");
+
+        // the first segment is read-only
+        let mut entry = echo();
+        entry[24..32].copy_from_slice(&0x10000u64.to_le_bytes());
+        assert_eq!(
+            invalid(&entry),
+            "entry point 0x10000 is outside the executable segments"
+        );
+
+        // truncated before the program headers
+        invalid(&echo()[..100]);
+    }
+
+    #[test]
+    fn extension_names() {
+        assert_eq!(extensions("rv64i2p1_m2p0_zba1p0"), ["i", "m", "zba"]);
+        assert_eq!(extensions("rv64i2_xtheadba1p0"), ["i", "xtheadba"]);
+    }
+}
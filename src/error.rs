@@ -1,4 +1,6 @@
+use jsonrpc_core::serde_json::{self, Map, Value};
 use jsonrpsee::types::ErrorObjectOwned;
+use serde::Serialize;
 use std::fmt::Display;
 
 #[derive(Debug)]
@@ -10,25 +12,92 @@ pub enum Error {
     InvalidRequest(&'static str),
     Script(i8),
     Vm(String),
+    ScriptMismatch(String),
+    /// The code is larger than the configured limit, in bytes.
+    CodeTooLarge {
+        size: usize,
+        limit: usize,
+    },
+    /// The script accessed memory beyond the configured VM memory, in bytes.
+    MemoryExceeded(usize),
+    /// The script set content larger than the configured limit, in bytes.
+    ContentTooLarge(usize),
+    /// The script's debug output exceeded the configured limit, in bytes.
+    DebugOutputTooLarge(usize),
+    /// The code is not a RISC-V 64 ELF ckb-vm can load.
+    InvalidElf(String),
+    /// A code hash search gave up after scanning this many type ID cells.
+    CodeSearchLimit(usize),
+    /// An error reported with additional data, see [`Error::with_data`].
+    WithData(Box<Error>, Map<String, Value>),
 }
 
-impl From<Error> for ErrorObjectOwned {
-    fn from(error: Error) -> Self {
-        let code = match error {
+impl Error {
+    /// Adds `value` under `key` to the data of the JSON-RPC error, e.g. the bundle a failed
+    /// request was recorded to.
+    pub fn with_data(self, key: &str, value: impl Serialize) -> Self {
+        let value = serde_json::to_value(value).expect("error data serializes to JSON");
+        match self {
+            Error::WithData(error, mut data) => {
+                data.insert(key.to_string(), value);
+                Error::WithData(error, data)
+            }
+            error => Error::WithData(Box::new(error), Map::from_iter([(key.to_string(), value)])),
+        }
+    }
+
+    /// The error without its data.
+    pub fn inner(&self) -> &Error {
+        match self {
+            Error::WithData(error, _) => error.inner(),
+            error => error,
+        }
+    }
+
+    fn code(&self) -> i32 {
+        match self {
             Error::JsonRpcRequestError => 1000,
             Error::Encoding(_) => 1001,
             Error::InvalidRequest(_) => 1002,
             Error::Script(_) => 1003,
             Error::Vm(_) => 1004,
-        };
-        let msg = match error {
+            Error::ScriptMismatch(_) => 1005,
+            Error::CodeTooLarge { .. } => 1006,
+            Error::MemoryExceeded(_) => 1007,
+            Error::ContentTooLarge(_) => 1008,
+            Error::DebugOutputTooLarge(_) => 1009,
+            Error::InvalidElf(_) => 1010,
+            Error::CodeSearchLimit(_) => 1011,
+            Error::WithData(error, _) => error.code(),
+        }
+    }
+
+    fn message(self) -> String {
+        match self {
             Error::JsonRpcRequestError => "".to_owned(),
             Error::Encoding(msg) | Error::InvalidRequest(msg) => msg.to_owned(),
-            Error::Vm(msg) => msg,
+            Error::Vm(msg) | Error::ScriptMismatch(msg) => msg,
             Error::Script(code) => format!("Script returns {}", code),
-        };
+            error @ (Error::CodeTooLarge { .. }
+            | Error::MemoryExceeded(_)
+            | Error::ContentTooLarge(_)
+            | Error::DebugOutputTooLarge(_)
+            | Error::InvalidElf(_)
+            | Error::CodeSearchLimit(_)) => error.to_string(),
+            Error::WithData(error, _) => error.message(),
+        }
+    }
+}
 
-        ErrorObjectOwned::owned(code, msg, None::<()>)
+impl From<Error> for ErrorObjectOwned {
+    fn from(error: Error) -> Self {
+        let code = error.code();
+        match error {
+            Error::WithData(error, data) => {
+                ErrorObjectOwned::owned(code, error.message(), Some(data))
+            }
+            error => ErrorObjectOwned::owned(code, error.message(), None::<()>),
+        }
     }
 }
 
@@ -40,6 +109,30 @@ impl Display for Error {
             Error::InvalidRequest(msg) => write!(f, "Invalid request: {}", msg),
             Error::Script(code) => write!(f, "Script returns {}", code),
             Error::Vm(msg) => write!(f, "VM error: {}", msg),
+            Error::ScriptMismatch(msg) => write!(f, "Script mismatch: {}", msg),
+            Error::CodeTooLarge { size, limit } => {
+                write!(
+                    f,
+                    "Code of {} bytes exceeds the limit of {} bytes",
+                    size, limit
+                )
+            }
+            Error::MemoryExceeded(limit) => {
+                write!(f, "Script exceeds the VM memory of {} bytes", limit)
+            }
+            Error::ContentTooLarge(limit) => {
+                write!(f, "Content exceeds the limit of {} bytes", limit)
+            }
+            Error::DebugOutputTooLarge(limit) => {
+                write!(f, "Debug output exceeds the limit of {} bytes", limit)
+            }
+            Error::InvalidElf(msg) => write!(f, "Invalid ELF: {}", msg),
+            Error::CodeSearchLimit(cells) => write!(
+                f,
+                "Code cell not found among the newest {} type ID cells, locate it by out point or type script",
+                cells
+            ),
+            Error::WithData(error, _) => error.fmt(f),
         }
     }
 }
@@ -0,0 +1,92 @@
+use std::net::TcpListener;
+
+use ckb_vm::{CoreMachine, DefaultMachine, Error, SupportMachine};
+use ckb_vm_debug_utils::{GdbStubHandler, GdbStubHandlerEventLoop};
+use gdbstub::conn::ConnectionExt;
+use gdbstub::stub::{DisconnectReason, GdbStub, GdbStubError};
+use gdbstub_arch::riscv::Riscv64;
+use jsonrpsee::tracing;
+
+use crate::ssri_vm::Stopped;
+
+/// Runs the loaded machine under a GDB remote serial protocol server on `addr`, like
+/// `ckb-debugger --mode gdb_gdbstub`. Waits for GDB to connect, and the script stays paused
+/// before its first instruction until GDB resumes it. The script runs to completion when GDB
+/// detaches.
+///
+/// The pc the script stopped at is unknown when GDB detached before.
+pub fn run_with_gdb<Inner: SupportMachine<REG = u64>>(
+    mut machine: DefaultMachine<Inner>,
+    addr: &str,
+) -> Stopped {
+    // the session blocks until the script exits, keep it off the other requests' thread
+    tokio::task::block_in_place(|| {
+        let stream = match accept(addr) {
+            Ok(stream) => stream,
+            Err(err) => {
+                let error = Error::External(format!("Failed to accept GDB on {addr}: {err}"));
+                return Stopped {
+                    result: Err(error),
+                    pc: None,
+                    cycles: 0,
+                };
+            }
+        };
+
+        machine.set_running(true);
+        let mut handler: GdbStubHandler<_, Riscv64> = GdbStubHandler::new(machine);
+        let connection: Box<dyn ConnectionExt<Error = std::io::Error>> = Box::new(stream);
+        let session = GdbStub::new(connection)
+            .run_blocking::<GdbStubHandlerEventLoop<_, Riscv64>>(&mut handler);
+        let pc = Some(*handler.pc());
+        let (pc, error) = match session {
+            Ok(DisconnectReason::Disconnect) => {
+                tracing::info!("GDB detached, running the script to completion");
+                (None, None)
+            }
+            Ok(DisconnectReason::TargetExited(_)) => (pc, None),
+            Ok(DisconnectReason::TargetTerminated(signal)) => (
+                pc,
+                Some(Error::External(format!(
+                    "Target terminated with signal {signal}"
+                ))),
+            ),
+            Ok(DisconnectReason::Kill) => (pc, Some(Error::External("Killed by GDB".to_string()))),
+            Err(GdbStubError::TargetError(err)) => (pc, Some(err)),
+            Err(err) => (
+                pc,
+                Some(Error::External(format!("GDB session failed: {err}"))),
+            ),
+        };
+        if let Some(error) = error {
+            return Stopped {
+                result: Err(error),
+                pc,
+                cycles: 0,
+            };
+        }
+
+        // returns right away when the script exited, the handler only reports cycles this way
+        match handler.run_till_exited() {
+            Ok((code, cycles)) => Stopped {
+                result: Ok(code),
+                pc,
+                cycles,
+            },
+            Err(err) => Stopped {
+                result: Err(err),
+                pc,
+                cycles: 0,
+            },
+        }
+    })
+}
+
+fn accept(addr: &str) -> std::io::Result<std::net::TcpStream> {
+    let listener = TcpListener::bind(addr)?;
+    tracing::info!("Waiting for GDB on {addr}");
+    let (stream, peer) = listener.accept()?;
+    tracing::info!("GDB connected from {peer}");
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
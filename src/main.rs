@@ -1,21 +1,56 @@
-use ckb_jsonrpc_types::{OutPoint, Script, TransactionView};
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use ckb_jsonrpc_types::{OutPoint, Script, TransactionView, Uint128};
+use ckb_sdk::rpc::ckb_indexer::{Order, SearchKey, SearchMode};
+use ckb_sdk::traits::CellQueryOptions;
+use ckb_sdk::Address;
+use ckb_types::core::ScriptHashType;
+use ckb_types::packed;
 use ckb_types::H256;
+use ckb_vm::Bytes;
+use clap::Parser;
+use jsonrpc_core::serde_json::{json, Value};
 use jsonrpsee::core::async_trait;
 use jsonrpsee::proc_macros::rpc;
 use jsonrpsee::server::Server;
 use jsonrpsee::tracing;
 use jsonrpsee::types::ErrorObjectOwned;
 
+mod bundle;
+mod code;
+mod config;
+mod coverage;
+mod elf;
 mod error;
+mod gdb;
+mod mock_chain;
+mod mock_tx;
+mod profile;
+mod resolver;
 mod rpc_client;
 mod ssri_vm;
+mod symbols;
+mod trace;
 mod types;
+mod udt;
 
+use bundle::{Bundle, BundleStore, Replay};
+use code::CodeCell;
+use config::Config;
+use coverage::Coverage;
 use error::Error;
+use mock_chain::MockChain;
+use resolver::Resolver;
 use rpc_client::RpcClient;
-use types::{CellOutputWithData, Hex};
+use types::{
+    BatchRequest, BatchResult, BinaryRequest, BinarySource, CellOutputWithData, CodeDescription,
+    CodeLocator, Hex, LockOrAddress, MockTxExport, PreparedCall, ScriptCall, ScriptRequest,
+    ScriptResponse, TipSnapshot, UdtMetadata,
+};
 
-use ssri_vm::execute_riscv_binary;
+use ssri_vm::{execute_riscv_binary, method_path, VmVersion};
 
 #[rpc(server)]
 pub trait Rpc {
@@ -25,6 +60,7 @@ pub trait Rpc {
         tx_hash: H256,
         index: u32,
         args: Vec<Hex>,
+        vm_version: Option<VmVersion>,
     ) -> Result<Option<Hex>, ErrorObjectOwned>;
 
     #[method(name = "run_script_level_script")]
@@ -45,6 +81,15 @@ pub trait Rpc {
         cell: CellOutputWithData,
     ) -> Result<Option<Hex>, ErrorObjectOwned>;
 
+    #[method(name = "run_script_level_cell_by_out_point")]
+    async fn run_script_level_cell_by_out_point(
+        &self,
+        tx_hash: H256,
+        index: u32,
+        args: Vec<Hex>,
+        cell_out_point: OutPoint,
+    ) -> Result<ScriptResponse, ErrorObjectOwned>;
+
     #[method(name = "run_script_level_tx")]
     async fn run_script_level_tx(
         &self,
@@ -53,58 +98,647 @@ pub trait Rpc {
         args: Vec<Hex>,
         tx: TransactionView,
     ) -> Result<Option<Hex>, ErrorObjectOwned>;
+
+    #[method(name = "run_script")]
+    async fn run_script(&self, request: ScriptRequest) -> Result<ScriptResponse, ErrorObjectOwned>;
+
+    #[method(name = "run_script_level_tx_by_hash")]
+    async fn run_script_level_tx_by_hash(
+        &self,
+        tx_hash: H256,
+        index: u32,
+        args: Vec<Hex>,
+        target_tx_hash: H256,
+    ) -> Result<Option<Hex>, ErrorObjectOwned>;
+
+    #[method(name = "run_script_batch")]
+    async fn run_script_batch(
+        &self,
+        request: BatchRequest,
+    ) -> Result<Vec<BatchResult>, ErrorObjectOwned>;
+
+    #[method(name = "run_binary")]
+    async fn run_binary(&self, request: BinaryRequest) -> Result<ScriptResponse, ErrorObjectOwned>;
+
+    #[method(name = "export_mock_tx")]
+    async fn export_mock_tx(
+        &self,
+        request: ScriptRequest,
+    ) -> Result<MockTxExport, ErrorObjectOwned>;
+
+    #[method(name = "get_bundle")]
+    async fn get_bundle(&self, bundle_id: String) -> Result<Bundle, ErrorObjectOwned>;
+
+    #[method(name = "get_coverage")]
+    async fn get_coverage(&self, reset: Option<bool>) -> Result<String, ErrorObjectOwned>;
+
+    #[method(name = "describe_code")]
+    async fn describe_code(&self, out_point: OutPoint)
+        -> Result<CodeDescription, ErrorObjectOwned>;
+
+    #[method(name = "udt_metadata")]
+    async fn udt_metadata(
+        &self,
+        tx_hash: H256,
+        index: u32,
+        script: Option<Script>,
+    ) -> Result<UdtMetadata, ErrorObjectOwned>;
+
+    #[method(name = "udt_balance")]
+    async fn udt_balance(
+        &self,
+        tx_hash: H256,
+        index: u32,
+        lock: LockOrAddress,
+        type_script: Script,
+    ) -> Result<Uint128, ErrorObjectOwned>;
 }
 
 pub struct RpcServerImpl {
     rpc: RpcClient,
+    config: Config,
+    bundles: Option<BundleStore>,
+    /// Coverage of the covered calls since the start or the last reset.
+    coverage: Arc<Mutex<Coverage>>,
+}
+
+/// How many times an execution is retried when its tip block is reorganized out of the chain.
+const SNAPSHOT_RETRIES: usize = 1;
+
+/// Names the bundle the request was recorded to in the response, or in the error data.
+fn with_bundle(
+    result: Result<ScriptResponse, Error>,
+    bundle: &Option<String>,
+) -> Result<ScriptResponse, ErrorObjectOwned> {
+    match result {
+        Ok(response) => Ok(ScriptResponse {
+            bundle: bundle.clone(),
+            ..response
+        }),
+        Err(err) => Err(error_with_bundle(err, bundle).into()),
+    }
+}
+
+/// Names the bundle in the error data only, for responses without a `bundle` field.
+fn or_bundle<T>(result: Result<T, Error>, bundle: &Option<String>) -> Result<T, ErrorObjectOwned> {
+    result.map_err(|err| error_with_bundle(err, bundle).into())
+}
+
+fn error_with_bundle(error: Error, bundle: &Option<String>) -> Error {
+    match bundle {
+        Some(bundle) => error.with_data("bundle", bundle),
+        None => error,
+    }
+}
+
+fn code_at(tx_hash: H256, index: u32) -> CodeLocator {
+    CodeLocator::OutPoint(OutPoint {
+        tx_hash,
+        index: index.into(),
+    })
 }
 
 impl RpcServerImpl {
-    pub fn new(rpc: &str) -> Self {
+    pub fn new(rpc: RpcClient, config: Config, bundles: Option<BundleStore>) -> Self {
         Self {
-            rpc: RpcClient::new(rpc),
+            rpc,
+            config,
+            bundles,
+            coverage: Default::default(),
         }
     }
 
-    async fn run_script(
+    /// Runs `f` with a client recording the upstream traffic when a record directory is
+    /// configured, and saves the traffic as a bundle of `method` called with `params`.
+    async fn recorded<T, F, Fut>(
         &self,
+        method: &str,
+        params: Value,
+        f: F,
+    ) -> (Result<T, Error>, Option<String>)
+    where
+        F: FnOnce(RpcClient) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let Some(bundles) = &self.bundles else {
+            return (f(self.rpc.clone()).await, None);
+        };
+
+        let rpc = self.rpc.recording();
+        let result = f(rpc.clone()).await;
+        let bundle = Bundle {
+            method: method.to_string(),
+            request: params,
+            exchanges: rpc.exchanges(),
+        };
+        match bundles.save(&bundle) {
+            Ok(id) => {
+                tracing::info!("Recorded {method} to bundle {id}");
+                (result, Some(id))
+            }
+            Err(err) => {
+                tracing::error!("Failed to record {method}: {err}");
+                (result, None)
+            }
+        }
+    }
+
+    /// Runs `f` with a resolver pinned to the current tip, then checks that the tip is
+    /// unchanged, retrying when blocks were added or reorganized during execution, since live
+    /// cell lookups read the node's current state.
+    ///
+    /// `f` covers calls into the coverage it's given, which is added to the session's for the
+    /// attempt whose result is returned only.
+    ///
+    /// Without `retry`, e.g. for debugged calls that would wait for GDB again, a moved tip is
+    /// only reported as inconsistent.
+    async fn with_snapshot<T, F, Fut>(
+        &self,
+        rpc: &RpcClient,
+        retry: bool,
+        f: F,
+    ) -> Result<(T, TipSnapshot), Error>
+    where
+        F: Fn(Resolver, Arc<Mutex<Coverage>>) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let tip = rpc.get_tip_header().await?;
+            let block_number = tip.inner.number;
+            let resolver = Resolver::new(rpc.clone()).with_tip(block_number.into());
+            let coverage = Arc::new(Mutex::new(Coverage::default()));
+            let result = f(resolver, coverage.clone()).await;
+            let merge = || {
+                self.coverage
+                    .lock()
+                    .unwrap()
+                    .merge(&coverage.lock().unwrap())
+            };
+            let result = match result {
+                Ok(result) => result,
+                Err(err) => {
+                    merge();
+                    return Err(err);
+                }
+            };
+
+            let end = rpc.get_tip_header().await?;
+            let consistent = end.hash == tip.hash;
+            if consistent || !retry || attempt == SNAPSHOT_RETRIES {
+                merge();
+                let snapshot = TipSnapshot {
+                    block_number,
+                    block_hash: tip.hash,
+                    consistent,
+                };
+                return Ok((result, snapshot));
+            }
+
+            tracing::warn!(
+                "Tip moved from {:#x} to {:#x} during execution, retrying",
+                tip.hash,
+                end.hash
+            );
+            attempt += 1;
+        }
+    }
+
+    async fn run_script(&self, request: ScriptRequest) -> Result<ScriptResponse, ErrorObjectOwned> {
+        let params = json!([&request]);
+        let (result, bundle) = self
+            .recorded("run_script", params, |rpc| async move {
+                let retry = !request.call.debug.unwrap_or(false);
+                let (mut response, tip) = self
+                    .with_snapshot(&rpc, retry, |resolver, coverage| {
+                        let request = request.clone();
+                        async move {
+                            let code =
+                                code::load_code(resolver.rpc(), &request.code, resolver.tip())
+                                    .await?;
+                            self.run_call(&resolver, &code, request.call, &coverage)
+                                .await
+                        }
+                    })
+                    .await?;
+
+                response.tip = Some(tip);
+                Ok(response)
+            })
+            .await;
+
+        with_bundle(result, &bundle)
+    }
+
+    /// Runs every call on one code load, all reading the chain through the same resolver.
+    async fn run_script_batch(
+        &self,
+        request: BatchRequest,
+    ) -> Result<Vec<BatchResult>, ErrorObjectOwned> {
+        let params = json!([&request]);
+        let (results, bundle) = self
+            .recorded("run_script_batch", params, |rpc| async move {
+                let retry = !request.calls.iter().any(|call| call.debug.unwrap_or(false));
+                let (mut results, tip) = self
+                    .with_snapshot(&rpc, retry, |resolver, coverage| {
+                        let request = request.clone();
+                        async move {
+                            let code =
+                                code::load_code(resolver.rpc(), &request.code, resolver.tip())
+                                    .await?;
+                            let mut results = Vec::with_capacity(request.calls.len());
+                            for call in request.calls {
+                                let result = self.run_call(&resolver, &code, call, &coverage);
+                                results.push(result.await);
+                            }
+                            Ok(results)
+                        }
+                    })
+                    .await?;
+
+                for response in results.iter_mut().flatten() {
+                    response.tip = Some(tip.clone());
+                }
+                Ok(results)
+            })
+            .await;
+
+        let results = results.map_err(|err| error_with_bundle(err, &bundle))?;
+        Ok(results
+            .into_iter()
+            .map(|result| with_bundle(result, &bundle).into())
+            .collect())
+    }
+
+    /// Runs a binary supplied by the caller instead of code deployed on chain.
+    async fn run_binary(&self, request: BinaryRequest) -> Result<ScriptResponse, ErrorObjectOwned> {
+        if !self.config.enable_run_binary {
+            return Err(Error::InvalidRequest("run_binary is disabled").into());
+        }
+        let code = CodeCell::from_binary(self.load_binary(request.binary)?);
+
+        let params = json!([&request.call]);
+        let (result, bundle) = self
+            .recorded("run_binary", params, |rpc| async move {
+                let retry = !request.call.debug.unwrap_or(false);
+                let (mut response, tip) = self
+                    .with_snapshot(&rpc, retry, |resolver, coverage| {
+                        let call = request.call.clone();
+                        let code = &code;
+                        async move { self.run_call(&resolver, code, call, &coverage).await }
+                    })
+                    .await?;
+
+                response.tip = Some(tip);
+                Ok(response)
+            })
+            .await;
+
+        with_bundle(result, &bundle)
+    }
+
+    /// Exports the context `run_script` would run the code in as a ckb-debugger mock transaction.
+    async fn export_mock_tx(
+        &self,
+        request: ScriptRequest,
+    ) -> Result<MockTxExport, ErrorObjectOwned> {
+        let params = json!([&request]);
+        let (result, bundle) = self
+            .recorded("export_mock_tx", params, |rpc| async move {
+                let resolver = Resolver::new(rpc.clone());
+                let code = code::load_code(&rpc, &request.code, None).await?;
+                let call = self
+                    .prepare_call(&resolver, &code, request.call, &self.coverage)
+                    .await?;
+                mock_tx::build_mock_tx(&code, &call).await
+            })
+            .await;
+
+        or_bundle(result, &bundle)
+    }
+
+    /// The session's coverage as an lcov tracefile, emptying it when `reset`.
+    fn get_coverage(&self, reset: bool) -> String {
+        let mut coverage = self.coverage.lock().unwrap();
+        let lcov = coverage.lcov();
+        if reset {
+            *coverage = Coverage::default();
+        }
+        lcov
+    }
+
+    /// Validates the code cell at `out_point` as a RISC-V 64 ELF and describes it.
+    async fn describe_code(
+        &self,
+        out_point: OutPoint,
+    ) -> Result<CodeDescription, ErrorObjectOwned> {
+        let params = json!([&out_point]);
+        let (result, bundle) = self
+            .recorded("describe_code", params, |rpc| async move {
+                let locator = CodeLocator::OutPoint(out_point.clone());
+                let code = code::load_code(&rpc, &locator, None).await?;
+                let elf = elf::describe(&code.data)?;
+                Ok(CodeDescription {
+                    out_point,
+                    code_live: code.live,
+                    data_hash: code.data_hash(),
+                    size: (code.data.len() as u64).into(),
+                    entry_point: elf.entry_point.into(),
+                    sections: elf.sections,
+                    has_symbols: elf.has_symbols,
+                    extensions: elf.extensions,
+                })
+            })
+            .await;
+
+        or_bundle(result, &bundle)
+    }
+
+    fn get_bundle(&self, bundle_id: &str) -> Result<Bundle, Error> {
+        self.bundles
+            .as_ref()
+            .ok_or(Error::InvalidRequest("Recording is disabled"))?
+            .get(bundle_id)
+    }
+
+    /// Reads an inline binary, only accepting paths that stay inside the binary directory.
+    fn load_binary(&self, binary: BinarySource) -> Result<Bytes, Error> {
+        match binary {
+            BinarySource::Elf { elf } => Ok(elf.hex.into()),
+            BinarySource::Path { path } => {
+                let dir = self
+                    .config
+                    .binary_dir
+                    .as_ref()
+                    .ok_or(Error::InvalidRequest("No binary directory configured"))?
+                    .canonicalize()
+                    .map_err(|_| Error::InvalidRequest("Binary directory not found"))?;
+                let path = dir
+                    .join(path)
+                    .canonicalize()
+                    .map_err(|_| Error::InvalidRequest("Binary not found"))?;
+                if !path.starts_with(&dir) {
+                    return Err(Error::InvalidRequest(
+                        "Binary is outside the binary directory",
+                    ));
+                }
+                let data = std::fs::read(path)
+                    .map_err(|_| Error::InvalidRequest("Binary not readable"))?;
+                Ok(data.into())
+            }
+        }
+    }
+
+    async fn run_call(
+        &self,
+        resolver: &Resolver,
+        code: &CodeCell,
+        call: ScriptCall,
+        coverage: &Arc<Mutex<Coverage>>,
+    ) -> Result<ScriptResponse, Error> {
+        let call = self.prepare_call(resolver, code, call, coverage).await?;
+
+        if !code.live {
+            tracing::info!(
+                "{} is dead, running code from its creating transaction",
+                code.describe()
+            );
+        }
+        tracing::info!(
+            "Running script on {} with args {:?}",
+            code.describe(),
+            call.args
+        );
+
+        let script_group = call.tx.as_ref().and_then(|tx| tx.group.clone());
+        let cell_live = call.cell_live;
+        let execution = execute_riscv_binary(code.data.clone(), call)?;
+
+        Ok(ScriptResponse {
+            content: execution.content.map(Into::into),
+            out_point: code.out_point.clone(),
+            code_live: code.live,
+            cell_live,
+            script_group,
+            tip: None,
+            bundle: None,
+            trace: execution.trace,
+            profile: execution.profile,
+            coverage: execution.coverage,
+            cycles: execution.cycles.into(),
+        })
+    }
+
+    /// Fetches the context of `call` and works out the script running the code and its VM.
+    /// Covered calls add their coverage to `coverage`.
+    async fn prepare_call(
+        &self,
+        resolver: &Resolver,
+        code: &CodeCell,
+        mut call: ScriptCall,
+        coverage: &Arc<Mutex<Coverage>>,
+    ) -> Result<PreparedCall, Error> {
+        let overrides = call
+            .overrides
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|v| (v.out_point.into(), v.cell.into()))
+            .collect();
+        let resolver = resolver
+            .clone()
+            .with_overrides(overrides)
+            .with_dead_cells(call.allow_dead_cells.unwrap_or(false));
+
+        let mut cell_live = None;
+        let cell_out_point = call.cell_out_point.take();
+        if let Some(cell_out_point) = &cell_out_point {
+            // a spent cell is reported rather than rejected, like code located by out point
+            let out_point = cell_out_point.clone().into();
+            let (cell, live) = match resolver.get_live_cell(&out_point).await? {
+                Some(cell) => (cell, true),
+                None => (resolver.resolve_cell(&out_point).await?, false),
+            };
+            cell_live = Some(live);
+            call.cell = Some(CellOutputWithData {
+                cell_output: cell.output.into(),
+                hex_data: Some(cell.data.into()),
+            });
+        }
+        if let Some(tx_hash) = call.tx_hash.take() {
+            call.tx = Some(resolver::get_transaction(resolver.rpc(), &tx_hash).await?);
+        }
+
+        let mut tx = match call.tx.take() {
+            Some(tx) => Some(resolver.resolve_transaction(tx.inner.into()).await?),
+            None => None,
+        };
+
+        let scripts = call.context_scripts(tx.as_ref());
+        let has_scripts = !scripts.is_empty();
+        let referencing = scripts
+            .into_iter()
+            .find(|script| code.is_referenced_by(script));
+        if call.strict.unwrap_or(true) && has_scripts && referencing.is_none() {
+            return Err(Error::ScriptMismatch(format!(
+                "no script in the context references {} (data hash {:#x})",
+                code.describe(),
+                code.data_hash()
+            )));
+        }
+
+        // the script being run decides the VM like on chain
+        let script = call.script.or(referencing);
+        let vm_version = call.vm_version.unwrap_or_else(|| {
+            script
+                .as_ref()
+                .map(|script| ScriptHashType::from(script.hash_type.clone()).into())
+                .unwrap_or_default()
+        });
+
+        let script: Option<packed::Script> = script.map(Into::into);
+        if let (Some(tx), Some(script)) = (tx.as_mut(), script.as_ref()) {
+            tx.set_group(script);
+        }
+
+        let gdb = match call.debug.unwrap_or(false) {
+            true if call.profile.unwrap_or(false) || call.coverage.unwrap_or(false) => {
+                return Err(Error::InvalidRequest(
+                    "Debugged calls can't be profiled or covered",
+                ))
+            }
+            true => Some(
+                self.config
+                    .gdb_listen
+                    .clone()
+                    .ok_or(Error::InvalidRequest("Debugging is not enabled"))?,
+            ),
+            false => None,
+        };
+
+        Ok(PreparedCall {
+            resolver,
+            args: call.args.into_iter().map(|v| v.hex.into()).collect(),
+            script,
+            cell: call.cell,
+            cell_out_point,
+            cell_live,
+            tx,
+            vm_version,
+            backend: self.config.vm_backend,
+            trace_limit: call
+                .trace
+                .unwrap_or(false)
+                .then_some(self.config.trace_limit),
+            profile: call.profile.unwrap_or(false),
+            gdb,
+            coverage: call.coverage.unwrap_or(false).then(|| coverage.clone()),
+            limits: self.config.limits(),
+        })
+    }
+
+    async fn get_udt_metadata(
+        &self,
+        rpc: &RpcClient,
         tx_hash: H256,
         index: u32,
-        args: Vec<Hex>,
         script: Option<Script>,
-        cell: Option<CellOutputWithData>,
-        tx: Option<TransactionView>,
-    ) -> Result<Option<Hex>, ErrorObjectOwned> {
-        let ssri_cell = self
-            .rpc
-            .get_live_cell(
-                &OutPoint {
-                    tx_hash: tx_hash.0.into(),
-                    index: index.into(),
-                },
-                true,
-            )
-            .await?;
-
-        tracing::info!("Running script on {tx_hash}:{index} with args {args:?}");
-
-        let ssri_binary = ssri_cell
-            .cell
-            .ok_or(Error::InvalidRequest("Cell not found"))?
-            .data
-            .ok_or(Error::InvalidRequest("Cell doesn't have data"))?
-            .content
-            .into_bytes();
-
-        let args = args.into_iter().map(|v| v.hex.into()).collect();
-        let script = script.map(Into::into);
-        let cell = cell.map(Into::into);
-        let tx = tx.map(|v| v.inner.into());
-
-        Ok(
-            execute_riscv_binary(self.rpc.clone(), ssri_binary, args, script, cell, tx)?
-                .map(|v| v.into()),
-        )
+    ) -> Result<UdtMetadata, Error> {
+        let resolver = Resolver::new(rpc.clone());
+        let code = code::load_code(rpc, &code_at(tx_hash, index), None).await?;
+        let call = |method: &str| {
+            let call = ScriptCall {
+                script: script.clone(),
+                ..ScriptCall::new(vec![method_path(method).into()])
+            };
+            self.run_call(&resolver, &code, call, &self.coverage)
+        };
+
+        let name = udt::decode_string(call(udt::NAME).await?.content)?;
+        let symbol = udt::decode_string(call(udt::SYMBOL).await?.content)?;
+        let decimals = udt::decode_u8(call(udt::DECIMALS).await?.content)?;
+        // icon is optional in the UDT interface, so scripts without it are not an error
+        let icon = match call(udt::ICON).await.map(|v| v.content) {
+            Ok(Some(content)) => Some(udt::decode_string(Some(content))?),
+            Ok(None) => None,
+            Err(err) if matches!(err.inner(), Error::Script(_)) => None,
+            Err(err) => return Err(err),
+        };
+
+        Ok(UdtMetadata {
+            name,
+            symbol,
+            decimals,
+            icon,
+        })
+    }
+
+    async fn get_udt_balance(
+        &self,
+        rpc: &RpcClient,
+        tx_hash: H256,
+        index: u32,
+        lock: LockOrAddress,
+        type_script: Script,
+    ) -> Result<u128, Error> {
+        let lock = match lock {
+            LockOrAddress::Script(script) => script.into(),
+            LockOrAddress::Address(address) => packed::Script::from(
+                &Address::from_str(&address)
+                    .map_err(|_| Error::InvalidRequest("Invalid address"))?,
+            ),
+        };
+        let mut query = CellQueryOptions::new_lock(lock);
+        query.script_search_mode = Some(SearchMode::Exact);
+        query.secondary_script = Some(type_script.clone().into());
+        query.with_data = Some(true);
+        let search_key = SearchKey::from(query);
+
+        let resolver = Resolver::new(rpc.clone());
+        let code = code::load_code(rpc, &code_at(tx_hash, index), None).await?;
+        let mut balance = 0u128;
+        let mut cursor = None;
+        loop {
+            let page = rpc
+                .get_cells(
+                    search_key.clone(),
+                    Order::Asc,
+                    udt::BALANCE_PAGE_SIZE,
+                    cursor,
+                )
+                .await?;
+            if page.objects.is_empty() {
+                break;
+            }
+
+            // the indexer matches the type script by prefix, so other tokens' args may follow
+            let cells = page
+                .objects
+                .into_iter()
+                .filter(|cell| cell.output.type_.as_ref() == Some(&type_script));
+            for cell in cells {
+                let cell = CellOutputWithData {
+                    cell_output: cell.output,
+                    hex_data: cell.output_data.map(|data| Hex {
+                        hex: data.into_bytes().to_vec(),
+                    }),
+                };
+                let call = ScriptCall {
+                    cell: Some(cell),
+                    ..ScriptCall::new(vec![method_path(udt::BALANCE).into()])
+                };
+                let content = self
+                    .run_call(&resolver, &code, call, &self.coverage)
+                    .await?
+                    .content;
+                balance = balance
+                    .checked_add(udt::decode_u128(content)?)
+                    .ok_or(Error::Encoding("UDT balance overflow"))?;
+            }
+            cursor = Some(page.last_cursor);
+        }
+
+        Ok(balance)
     }
 }
 
@@ -115,9 +749,17 @@ impl RpcServer for RpcServerImpl {
         tx_hash: H256,
         index: u32,
         args: Vec<Hex>,
+        vm_version: Option<VmVersion>,
     ) -> Result<Option<Hex>, ErrorObjectOwned> {
-        self.run_script(tx_hash, index, args, None, None, None)
-            .await
+        self.run_script(ScriptRequest::new(
+            code_at(tx_hash, index),
+            ScriptCall {
+                vm_version,
+                ..ScriptCall::new(args)
+            },
+        ))
+        .await
+        .map(|v| v.content)
     }
 
     async fn run_script_level_script(
@@ -127,8 +769,15 @@ impl RpcServer for RpcServerImpl {
         args: Vec<Hex>,
         script: Script,
     ) -> Result<Option<Hex>, ErrorObjectOwned> {
-        self.run_script(tx_hash, index, args, Some(script), None, None)
-            .await
+        self.run_script(ScriptRequest::new(
+            code_at(tx_hash, index),
+            ScriptCall {
+                script: Some(script),
+                ..ScriptCall::new(args)
+            },
+        ))
+        .await
+        .map(|v| v.content)
     }
 
     async fn run_script_level_cell(
@@ -138,8 +787,32 @@ impl RpcServer for RpcServerImpl {
         args: Vec<Hex>,
         cell: CellOutputWithData,
     ) -> Result<Option<Hex>, ErrorObjectOwned> {
-        self.run_script(tx_hash, index, args, None, Some(cell), None)
-            .await
+        self.run_script(ScriptRequest::new(
+            code_at(tx_hash, index),
+            ScriptCall {
+                cell: Some(cell),
+                ..ScriptCall::new(args)
+            },
+        ))
+        .await
+        .map(|v| v.content)
+    }
+
+    async fn run_script_level_cell_by_out_point(
+        &self,
+        tx_hash: H256,
+        index: u32,
+        args: Vec<Hex>,
+        cell_out_point: OutPoint,
+    ) -> Result<ScriptResponse, ErrorObjectOwned> {
+        self.run_script(ScriptRequest::new(
+            code_at(tx_hash, index),
+            ScriptCall {
+                cell_out_point: Some(cell_out_point),
+                ..ScriptCall::new(args)
+            },
+        ))
+        .await
     }
 
     async fn run_script_level_tx(
@@ -149,8 +822,104 @@ impl RpcServer for RpcServerImpl {
         args: Vec<Hex>,
         tx: TransactionView,
     ) -> Result<Option<Hex>, ErrorObjectOwned> {
-        self.run_script(tx_hash, index, args, None, None, Some(tx))
-            .await
+        self.run_script(ScriptRequest::new(
+            code_at(tx_hash, index),
+            ScriptCall {
+                tx: Some(tx),
+                ..ScriptCall::new(args)
+            },
+        ))
+        .await
+        .map(|v| v.content)
+    }
+
+    async fn run_script_level_tx_by_hash(
+        &self,
+        tx_hash: H256,
+        index: u32,
+        args: Vec<Hex>,
+        target_tx_hash: H256,
+    ) -> Result<Option<Hex>, ErrorObjectOwned> {
+        self.run_script(ScriptRequest::new(
+            code_at(tx_hash, index),
+            ScriptCall {
+                tx_hash: Some(target_tx_hash),
+                ..ScriptCall::new(args)
+            },
+        ))
+        .await
+        .map(|v| v.content)
+    }
+
+    async fn run_script(&self, request: ScriptRequest) -> Result<ScriptResponse, ErrorObjectOwned> {
+        RpcServerImpl::run_script(self, request).await
+    }
+
+    async fn run_script_batch(
+        &self,
+        request: BatchRequest,
+    ) -> Result<Vec<BatchResult>, ErrorObjectOwned> {
+        RpcServerImpl::run_script_batch(self, request).await
+    }
+
+    async fn run_binary(&self, request: BinaryRequest) -> Result<ScriptResponse, ErrorObjectOwned> {
+        RpcServerImpl::run_binary(self, request).await
+    }
+
+    async fn export_mock_tx(
+        &self,
+        request: ScriptRequest,
+    ) -> Result<MockTxExport, ErrorObjectOwned> {
+        RpcServerImpl::export_mock_tx(self, request).await
+    }
+
+    async fn get_bundle(&self, bundle_id: String) -> Result<Bundle, ErrorObjectOwned> {
+        RpcServerImpl::get_bundle(self, &bundle_id).map_err(Into::into)
+    }
+
+    async fn get_coverage(&self, reset: Option<bool>) -> Result<String, ErrorObjectOwned> {
+        Ok(RpcServerImpl::get_coverage(self, reset.unwrap_or(false)))
+    }
+
+    async fn describe_code(
+        &self,
+        out_point: OutPoint,
+    ) -> Result<CodeDescription, ErrorObjectOwned> {
+        RpcServerImpl::describe_code(self, out_point).await
+    }
+
+    async fn udt_metadata(
+        &self,
+        tx_hash: H256,
+        index: u32,
+        script: Option<Script>,
+    ) -> Result<UdtMetadata, ErrorObjectOwned> {
+        let params = json!([&tx_hash, index, &script]);
+        let (result, bundle) = self
+            .recorded("udt_metadata", params, |rpc| async move {
+                self.get_udt_metadata(&rpc, tx_hash, index, script).await
+            })
+            .await;
+
+        or_bundle(result, &bundle)
+    }
+
+    async fn udt_balance(
+        &self,
+        tx_hash: H256,
+        index: u32,
+        lock: LockOrAddress,
+        type_script: Script,
+    ) -> Result<Uint128, ErrorObjectOwned> {
+        let params = json!([&tx_hash, index, &lock, &type_script]);
+        let (result, bundle) = self
+            .recorded("udt_balance", params, |rpc| async move {
+                self.get_udt_balance(&rpc, tx_hash, index, lock, type_script)
+                    .await
+            })
+            .await;
+
+        or_bundle(result, &bundle).map(Into::into)
     }
 }
 
@@ -161,21 +930,24 @@ async fn main() -> anyhow::Result<()> {
         .try_init()
         .expect("setting default subscriber failed");
 
-    let ckb_rpc = std::env::args()
-        .nth(1)
-        .unwrap_or_else(|| "https://testnet.ckbapp.dev/".to_string());
-    let server_addr = std::env::args()
-        .nth(2)
-        .unwrap_or_else(|| "0.0.0.0:9090".to_string());
-
-    run_server(&ckb_rpc, &server_addr).await?;
+    run_server(Config::parse()).await?;
     Ok(())
 }
 
-async fn run_server(ckb_rpc: &str, server_addr: &str) -> anyhow::Result<()> {
-    let server = Server::builder().build(server_addr).await?;
+async fn run_server(config: Config) -> anyhow::Result<()> {
+    let server = Server::builder().build(&config.server_addr).await?;
 
-    let handle = server.start(RpcServerImpl::new(ckb_rpc).into_rpc());
+    let rpc = if let Some(path) = &config.mock_chain {
+        tracing::info!("Serving chain lookups from mock chain {}", path.display());
+        RpcClient::mock(MockChain::load(path)?)
+    } else if let Some(path) = &config.replay {
+        tracing::info!("Replaying chain lookups from bundle {}", path.display());
+        RpcClient::replay(Replay::new(Bundle::load(path)?))
+    } else {
+        RpcClient::new(&config.ckb_rpc)
+    };
+    let bundles = config.record.clone().map(BundleStore::new).transpose()?;
+    let handle = server.start(RpcServerImpl::new(rpc, config, bundles).into_rpc());
 
     tokio::signal::ctrl_c().await.unwrap();
     handle.stop().unwrap();
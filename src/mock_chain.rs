@@ -0,0 +1,391 @@
+use std::path::Path;
+
+use ckb_jsonrpc_types::{
+    BlockNumber, CellData, CellInfo, CellOutput, CellWithStatus, HeaderView, JsonBytes, OutPoint,
+    ResponseFormat, Script, Status, TransactionView, TransactionWithStatusResponse, TxStatus,
+    Uint32,
+};
+use ckb_sdk::rpc::ckb_indexer::{Cell, Order, Pagination, ScriptType, SearchKey, SearchMode};
+use ckb_types::{packed, prelude::*, H256};
+use jsonrpc_core::serde_json::{self, Value};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::error::Error;
+
+/// A chain state loaded from a JSON fixture, answering `RpcClient` lookups without a node.
+#[derive(Deserialize)]
+pub struct MockChain {
+    pub headers: Vec<HeaderView>,
+    /// Live cells, in the order the indexer returns them within a block.
+    pub cells: Vec<MockCell>,
+    /// Transactions served by `get_transaction`, e.g. to resolve spent inputs.
+    #[serde(default)]
+    pub transactions: Vec<TransactionView>,
+}
+
+#[derive(Deserialize)]
+pub struct MockCell {
+    pub out_point: OutPoint,
+    pub output: CellOutput,
+    #[serde(default)]
+    pub data: JsonBytes,
+    pub block_number: BlockNumber,
+}
+
+impl MockChain {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+
+    /// Answers a JSON-RPC request of `RpcClient`.
+    pub fn request(&self, method: &str, params: Value) -> Result<Value, Error> {
+        let result = match method {
+            "get_live_cell" => {
+                let (out_point, with_data) = parse(params)?;
+                serde_json::to_value(self.get_live_cell(&out_point, with_data))
+            }
+            "get_tip_header" => serde_json::to_value(self.get_tip_header()?),
+            "get_transaction" => {
+                let (tx_hash,) = parse(params)?;
+                serde_json::to_value(self.get_transaction(&tx_hash))
+            }
+            "get_cells" => {
+                let (search_key, order, limit, cursor): (SearchKey, Order, Uint32, _) =
+                    parse(params)?;
+                serde_json::to_value(self.get_cells(&search_key, order, limit.into(), cursor)?)
+            }
+            _ => return Err(Error::InvalidRequest("Method not served by the mock chain")),
+        };
+        Ok(result.expect("RPC results serialize to JSON"))
+    }
+
+    pub fn get_live_cell(&self, out_point: &OutPoint, with_data: bool) -> CellWithStatus {
+        let Some(cell) = self.cells.iter().find(|cell| &cell.out_point == out_point) else {
+            return CellWithStatus {
+                cell: None,
+                status: "unknown".to_string(),
+            };
+        };
+
+        let data = with_data.then(|| CellData {
+            hash: packed::CellOutput::calc_data_hash(cell.data.as_bytes()).unpack(),
+            content: cell.data.clone(),
+        });
+        CellWithStatus {
+            cell: Some(CellInfo {
+                output: cell.output.clone(),
+                data,
+            }),
+            status: "live".to_string(),
+        }
+    }
+
+    pub fn get_tip_header(&self) -> Result<HeaderView, Error> {
+        self.headers
+            .iter()
+            .max_by_key(|header| u64::from(header.inner.number))
+            .cloned()
+            .ok_or(Error::InvalidRequest("Mock chain has no headers"))
+    }
+
+    pub fn get_transaction(&self, tx_hash: &H256) -> Option<TransactionWithStatusResponse> {
+        let tx = self.transactions.iter().find(|tx| &tx.hash == tx_hash)?;
+        Some(TransactionWithStatusResponse {
+            transaction: Some(ResponseFormat::json(tx.clone())),
+            cycles: None,
+            time_added_to_pool: None,
+            tx_status: TxStatus {
+                status: Status::Committed,
+                block_number: None,
+                block_hash: None,
+                reason: None,
+            },
+            fee: None,
+            min_replace_fee: None,
+        })
+    }
+
+    /// Matches cells against the search key like the indexer does, ordered by block number.
+    /// The cursor is the number of cells already returned.
+    pub fn get_cells(
+        &self,
+        search_key: &SearchKey,
+        order: Order,
+        limit: u32,
+        cursor: Option<JsonBytes>,
+    ) -> Result<Pagination<Cell>, Error> {
+        let offset = match cursor {
+            Some(cursor) => <[u8; 8]>::try_from(cursor.as_bytes())
+                .map(u64::from_be_bytes)
+                .map_err(|_| Error::InvalidRequest("Invalid cursor"))?
+                as usize,
+            None => 0,
+        };
+
+        let mut cells: Vec<(usize, &MockCell)> = self
+            .cells
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| matches_search_key(cell, search_key))
+            .collect();
+        cells.sort_by_key(|(position, cell)| (u64::from(cell.block_number), *position));
+        if matches!(order, Order::Desc) {
+            cells.reverse();
+        }
+
+        let with_data = search_key.with_data.unwrap_or(true);
+        let objects: Vec<Cell> = cells
+            .into_iter()
+            .skip(offset)
+            .take(limit as usize)
+            .map(|(position, cell)| Cell {
+                output: cell.output.clone(),
+                output_data: with_data.then(|| cell.data.clone()),
+                out_point: cell.out_point.clone(),
+                block_number: cell.block_number,
+                tx_index: (position as u32).into(),
+            })
+            .collect();
+
+        let last_cursor = ((offset + objects.len()) as u64).to_be_bytes().to_vec();
+        Ok(Pagination {
+            objects,
+            last_cursor: JsonBytes::from_vec(last_cursor),
+        })
+    }
+}
+
+fn matches_search_key(cell: &MockCell, search_key: &SearchKey) -> bool {
+    let (primary, secondary) = match search_key.script_type {
+        ScriptType::Lock => (Some(&cell.output.lock), cell.output.type_.as_ref()),
+        ScriptType::Type => (cell.output.type_.as_ref(), Some(&cell.output.lock)),
+    };
+    let mode = search_key.script_search_mode.clone().unwrap_or_default();
+    if !primary.is_some_and(|script| matches_script(script, &search_key.script, &mode)) {
+        return false;
+    }
+
+    let Some(filter) = &search_key.filter else {
+        return true;
+    };
+    if let Some(script) = &filter.script {
+        if !secondary.is_some_and(|v| matches_script(v, script, &SearchMode::Prefix)) {
+            return false;
+        }
+    }
+    if let Some(range) = &filter.script_len_range {
+        let len = secondary.map_or(0, |script| {
+            packed::Script::from(script.clone()).total_size()
+        });
+        if !in_range(len as u64, range) {
+            return false;
+        }
+    }
+    if let Some(data) = &filter.output_data {
+        let mode = filter.output_data_filter_mode.clone().unwrap_or_default();
+        if !matches_bytes(cell.data.as_bytes(), data.as_bytes(), &mode) {
+            return false;
+        }
+    }
+    if let Some(range) = &filter.output_data_len_range {
+        if !in_range(cell.data.len() as u64, range) {
+            return false;
+        }
+    }
+    if let Some(range) = &filter.output_capacity_range {
+        if !in_range(cell.output.capacity.into(), range) {
+            return false;
+        }
+    }
+    if let Some([start, end]) = &filter.block_range {
+        let number = u64::from(cell.block_number);
+        if number < u64::from(*start) || number >= u64::from(*end) {
+            return false;
+        }
+    }
+    true
+}
+
+fn matches_script(script: &Script, search: &Script, mode: &SearchMode) -> bool {
+    script.code_hash == search.code_hash
+        && script.hash_type == search.hash_type
+        && matches_bytes(script.args.as_bytes(), search.args.as_bytes(), mode)
+}
+
+fn matches_bytes(bytes: &[u8], search: &[u8], mode: &SearchMode) -> bool {
+    match mode {
+        SearchMode::Prefix => bytes.starts_with(search),
+        SearchMode::Exact => bytes == search,
+        SearchMode::Partial => {
+            search.is_empty() || bytes.windows(search.len()).any(|v| v == search)
+        }
+    }
+}
+
+/// Indexer ranges are half-open, `[start, end)`.
+fn in_range<T: Into<u64> + Copy>(value: u64, [start, end]: &[T; 2]) -> bool {
+    value >= (*start).into() && value < (*end).into()
+}
+
+fn parse<T: DeserializeOwned>(params: Value) -> Result<T, Error> {
+    serde_json::from_value(params).map_err(|_| Error::Encoding("Invalid mock chain request params"))
+}
+
+#[cfg(test)]
+mod tests {
+    use ckb_jsonrpc_types::ScriptHashType;
+    use ckb_sdk::rpc::ckb_indexer::SearchKeyFilter;
+
+    use super::*;
+
+    fn script(code_hash: u8, args: &[u8]) -> Script {
+        Script {
+            code_hash: H256([code_hash; 32]),
+            hash_type: ScriptHashType::Type,
+            args: JsonBytes::from_vec(args.to_vec()),
+        }
+    }
+
+    fn cell(index: u32, lock: Script, type_: Option<Script>, data: &[u8], block: u64) -> MockCell {
+        MockCell {
+            out_point: OutPoint {
+                tx_hash: H256([1; 32]),
+                index: index.into(),
+            },
+            output: CellOutput {
+                capacity: 100.into(),
+                lock,
+                type_,
+            },
+            data: JsonBytes::from_vec(data.to_vec()),
+            block_number: block.into(),
+        }
+    }
+
+    fn search_key(script: Script, script_type: ScriptType, mode: SearchMode) -> SearchKey {
+        SearchKey {
+            script,
+            script_type,
+            script_search_mode: Some(mode),
+            filter: None,
+            with_data: None,
+            group_by_transaction: None,
+        }
+    }
+
+    /// The indices of the out points of the cells matching `search_key`, in order.
+    fn indices(chain: &MockChain, search_key: &SearchKey, order: Order) -> Vec<u32> {
+        chain
+            .get_cells(search_key, order, 100, None)
+            .unwrap()
+            .objects
+            .into_iter()
+            .map(|cell| cell.out_point.index.into())
+            .collect()
+    }
+
+    fn chain() -> MockChain {
+        MockChain {
+            headers: vec![],
+            cells: vec![
+                cell(0, script(1, b"alice"), None, b"", 3),
+                cell(1, script(1, b"bob"), Some(script(2, b"")), b"hello", 1),
+                cell(
+                    2,
+                    script(1, b"alice-2"),
+                    Some(script(2, b"xy")),
+                    b"world",
+                    2,
+                ),
+                cell(3, script(3, b"alice"), None, b"", 1),
+            ],
+            transactions: vec![],
+        }
+    }
+
+    #[test]
+    fn search_modes() {
+        let chain = chain();
+        let lock = |args: &[u8], mode| search_key(script(1, args), ScriptType::Lock, mode);
+
+        assert_eq!(
+            indices(&chain, &lock(b"alice", SearchMode::Prefix), Order::Asc),
+            [2, 0]
+        );
+        assert_eq!(
+            indices(&chain, &lock(b"alice", SearchMode::Exact), Order::Asc),
+            [0]
+        );
+        assert_eq!(
+            indices(&chain, &lock(b"ice", SearchMode::Partial), Order::Asc),
+            [2, 0]
+        );
+        assert_eq!(
+            indices(&chain, &lock(b"", SearchMode::Partial), Order::Asc),
+            [1, 2, 0]
+        );
+
+        let type_ = search_key(script(2, b""), ScriptType::Type, SearchMode::Exact);
+        assert_eq!(indices(&chain, &type_, Order::Asc), [1]);
+    }
+
+    #[test]
+    fn filters() {
+        let chain = chain();
+        let mut key = search_key(script(1, b""), ScriptType::Lock, SearchMode::Prefix);
+
+        key.filter = Some(SearchKeyFilter {
+            script: Some(script(2, b"x")),
+            ..Default::default()
+        });
+        assert_eq!(indices(&chain, &key, Order::Asc), [2]);
+
+        key.filter = Some(SearchKeyFilter {
+            output_data: Some(JsonBytes::from_vec(b"orl".to_vec())),
+            output_data_filter_mode: Some(SearchMode::Partial),
+            ..Default::default()
+        });
+        assert_eq!(indices(&chain, &key, Order::Asc), [2]);
+
+        key.filter = Some(SearchKeyFilter {
+            output_data_len_range: Some([1.into(), 6.into()]),
+            ..Default::default()
+        });
+        assert_eq!(indices(&chain, &key, Order::Asc), [1, 2]);
+
+        key.filter = Some(SearchKeyFilter {
+            block_range: Some([2.into(), 3.into()]),
+            ..Default::default()
+        });
+        assert_eq!(indices(&chain, &key, Order::Asc), [2]);
+    }
+
+    #[test]
+    fn cursor_paging() {
+        let chain = chain();
+        let key = search_key(script(1, b""), ScriptType::Lock, SearchMode::Prefix);
+
+        for (order, expected) in [(Order::Asc, [1, 2, 0]), (Order::Desc, [0, 2, 1])] {
+            let mut seen = vec![];
+            let mut cursor = None;
+            loop {
+                let page = chain.get_cells(&key, order.clone(), 2, cursor).unwrap();
+                if page.objects.is_empty() {
+                    break;
+                }
+                seen.extend(
+                    page.objects
+                        .iter()
+                        .map(|cell| u32::from(cell.out_point.index)),
+                );
+                cursor = Some(page.last_cursor);
+            }
+            assert_eq!(seen, expected);
+        }
+
+        let cursor = Some(JsonBytes::from_vec(vec![0]));
+        assert!(chain.get_cells(&key, Order::Asc, 2, cursor).is_err());
+    }
+}
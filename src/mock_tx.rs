@@ -0,0 +1,169 @@
+use ckb_mock_tx_types::{MockCellDep, MockInfo, MockInput, MockTransaction};
+use ckb_types::core::{DepType, ScriptHashType};
+use ckb_types::packed::{
+    Bytes as PackedBytes, CellDep, CellInput, CellOutput, OutPoint, OutPointVec, Script,
+    Transaction,
+};
+use ckb_types::prelude::*;
+
+use crate::code::CodeCell;
+use crate::error::Error;
+use crate::resolver::{ResolvedCell, ResolvedTransaction, ScriptGroupType};
+use crate::types::{MockTxExport, PreparedCall};
+
+/// Builds a ckb-debugger mock transaction running the code like `call` does.
+///
+/// Tx-level calls export their transaction. Otherwise a transaction spending the call's cell,
+/// or a cell locked by the script, is made up so the script has a group to run in. The code
+/// cell is added to the cell deps when the transaction doesn't depend on it already.
+pub async fn build_mock_tx(code: &CodeCell, call: &PreparedCall) -> Result<MockTxExport, Error> {
+    let script = call.script.clone().unwrap_or_else(|| {
+        Script::new_builder()
+            .code_hash(code.data_hash().pack())
+            .hash_type(ScriptHashType::from(call.vm_version).into())
+            .build()
+    });
+
+    let (mut transaction, inputs) = match &call.tx {
+        Some(tx) => (tx.transaction.clone(), tx.inputs.clone()),
+        None => spending_tx(call, &script),
+    };
+
+    let mut cell_deps = Vec::new();
+    for cell_dep in transaction.raw().cell_deps() {
+        let cell = call.resolver.resolve_cell(&cell_dep.out_point()).await?;
+        let dep_type = DepType::try_from(cell_dep.dep_type())
+            .map_err(|_| Error::Encoding("Invalid dep type"))?;
+        if dep_type == DepType::DepGroup {
+            let out_points = OutPointVec::from_slice(&cell.data)
+                .map_err(|_| Error::Encoding("Invalid dep group data"))?;
+            for out_point in out_points {
+                let member = call.resolver.resolve_cell(&out_point).await?;
+                cell_deps.push(mock_cell_dep(code_dep(out_point), member));
+            }
+        }
+        cell_deps.push(mock_cell_dep(cell_dep, cell));
+    }
+
+    if let (Some(out_point), Some(output)) = (&code.out_point, &code.output) {
+        let out_point: OutPoint = out_point.clone().into();
+        if !cell_deps
+            .iter()
+            .any(|dep| dep.cell_dep.out_point() == out_point)
+        {
+            let cell_dep = code_dep(out_point);
+            let raw = transaction.raw();
+            let deps = raw.cell_deps().as_builder().push(cell_dep.clone()).build();
+            transaction = transaction
+                .as_builder()
+                .raw(raw.as_builder().cell_deps(deps).build())
+                .build();
+            cell_deps.push(mock_cell_dep(
+                cell_dep,
+                ResolvedCell {
+                    output: output.clone().into(),
+                    data: code.data.clone(),
+                },
+            ));
+        }
+    }
+
+    let mock_inputs = transaction
+        .raw()
+        .inputs()
+        .into_iter()
+        .zip(&inputs)
+        .map(|(input, cell)| MockInput {
+            input,
+            output: cell.output.clone(),
+            data: cell.data.clone(),
+            header: None,
+        })
+        .collect();
+
+    let mut resolved = ResolvedTransaction {
+        transaction: transaction.clone(),
+        inputs,
+        cell_deps: vec![],
+        group: None,
+    };
+    resolved.set_group(&script);
+    let script_group = resolved.group.expect("set_group always sets a group");
+    let (cell_type, cell_index) = match (
+        script_group.input_indices.first(),
+        script_group.output_indices.first(),
+    ) {
+        (Some(index), _) => ("input", *index),
+        (None, Some(index)) => ("output", *index),
+        (None, None) => {
+            return Err(Error::InvalidRequest(
+                "The script doesn't run in the exported transaction",
+            ))
+        }
+    };
+    let script_group_type = match script_group.group_type {
+        ScriptGroupType::Lock => "lock",
+        ScriptGroupType::Type => "type",
+    };
+
+    let mock_tx = MockTransaction {
+        mock_info: MockInfo {
+            inputs: mock_inputs,
+            cell_deps,
+            header_deps: vec![],
+            extensions: vec![],
+        },
+        tx: transaction,
+    };
+
+    Ok(MockTxExport {
+        mock_tx: mock_tx.into(),
+        script: format!("{cell_type}.{cell_index}.{script_group_type}"),
+        script_group,
+        args: call.args.iter().map(hex::encode).collect(),
+    })
+}
+
+/// A transaction spending the call's cell, or a cell locked by `script` for code and
+/// script-level calls.
+fn spending_tx(call: &PreparedCall, script: &Script) -> (Transaction, Vec<ResolvedCell>) {
+    let cell = match &call.cell {
+        Some(cell) => ResolvedCell::from(cell.clone()),
+        None => ResolvedCell {
+            output: CellOutput::new_builder().lock(script.clone()).build(),
+            data: Default::default(),
+        },
+    };
+    let previous_output = call
+        .cell_out_point
+        .clone()
+        .map(Into::into)
+        .unwrap_or_default();
+
+    let raw = Transaction::default().raw();
+    let raw = raw
+        .as_builder()
+        .inputs(vec![CellInput::new(previous_output, 0)].pack())
+        .build();
+    let transaction = Transaction::new_builder()
+        .raw(raw)
+        .witnesses(vec![PackedBytes::default()].pack())
+        .build();
+    (transaction, vec![cell])
+}
+
+fn code_dep(out_point: OutPoint) -> CellDep {
+    CellDep::new_builder()
+        .out_point(out_point)
+        .dep_type(DepType::Code.into())
+        .build()
+}
+
+fn mock_cell_dep(cell_dep: CellDep, cell: ResolvedCell) -> MockCellDep {
+    MockCellDep {
+        cell_dep,
+        output: cell.output,
+        data: cell.data,
+        header: None,
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+
+use ckb_vm::instructions::{extract_opcode, instruction_length, insts, Instruction};
+
+use crate::symbols::Symbols;
+
+struct Node {
+    function: String,
+    parent: Option<usize>,
+    children: HashMap<String, usize>,
+    /// Cycles spent in the function itself, excluding callees.
+    cycles: u64,
+}
+
+/// Attributes the cycles of an execution to call stacks, the way ckb-vm-pprof does: a jump to
+/// the start of a function symbol is a call, and a jump to the return address of a frame
+/// returns from it and the frames above.
+pub struct Profiler {
+    symbols: Symbols,
+    nodes: Vec<Node>,
+    /// Node and return address of every frame, the last one running. The root frame is
+    /// never returned from.
+    stack: Vec<(usize, u64)>,
+}
+
+impl Profiler {
+    pub fn new(symbols: Symbols) -> Self {
+        Self {
+            symbols,
+            nodes: Vec::new(),
+            stack: Vec::new(),
+        }
+    }
+
+    /// Accounts for an instruction at `pc` that took `cycles` and moved on to `next_pc`.
+    pub fn step(&mut self, pc: u64, instruction: Instruction, cycles: u64, next_pc: u64) {
+        if self.stack.is_empty() {
+            let root = self.node(None, self.name(pc));
+            self.stack.push((root, 0));
+        }
+        let (node, _) = *self.stack.last().unwrap();
+        self.nodes[node].cycles += cycles;
+
+        let jumps = matches!(
+            extract_opcode(instruction),
+            insts::OP_JAL
+                | insts::OP_JALR_VERSION0
+                | insts::OP_JALR_VERSION1
+                | insts::OP_FAR_JUMP_ABS
+                | insts::OP_FAR_JUMP_REL
+        );
+        if jumps {
+            let link = pc + instruction_length(instruction) as u64;
+            self.jump(next_pc, link);
+        }
+    }
+
+    fn jump(&mut self, target: u64, link: u64) {
+        if let Some(function) = self.symbols.function_at(target) {
+            let function = function.to_string();
+            let (parent, _) = *self.stack.last().unwrap();
+            let node = self.node(Some(parent), function);
+            self.stack.push((node, link));
+        } else if let Some(frame) = self.stack.iter().skip(1).rposition(|v| v.1 == target) {
+            self.stack.truncate(frame + 1);
+        }
+    }
+
+    fn name(&self, pc: u64) -> String {
+        self.symbols
+            .function(pc)
+            .map_or_else(|| format!("{pc:#x}"), str::to_string)
+    }
+
+    fn node(&mut self, parent: Option<usize>, function: String) -> usize {
+        if let Some(&node) = parent.and_then(|v| self.nodes[v].children.get(&function)) {
+            return node;
+        }
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            function: function.clone(),
+            parent,
+            children: HashMap::new(),
+            cycles: 0,
+        });
+        if let Some(parent) = parent {
+            self.nodes[parent].children.insert(function, node);
+        }
+        node
+    }
+
+    /// Folded stacks, one `caller;callee cycles` line per stack, as flamegraph tools take
+    /// them.
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = (0..self.nodes.len())
+            .filter(|&node| self.nodes[node].cycles > 0)
+            .map(|node| {
+                let mut stack = vec![self.nodes[node].function.as_str()];
+                let mut parent = self.nodes[node].parent;
+                while let Some(node) = parent {
+                    stack.push(&self.nodes[node].function);
+                    parent = self.nodes[node].parent;
+                }
+                stack.reverse();
+                format!("{} {}", stack.join(";"), self.nodes[node].cycles)
+            })
+            .collect();
+        lines.sort();
+        lines.join("\n")
+    }
+}
@@ -0,0 +1,481 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use ckb_jsonrpc_types::{Either, TransactionView};
+use ckb_sdk::rpc::ckb_indexer::Order;
+use ckb_sdk::traits::{CellQueryOptions, ValueRangeOption};
+use ckb_types::core::DepType;
+use ckb_types::packed::{Byte32, CellDep, CellOutput, OutPoint, OutPointVec, Script, Transaction};
+use ckb_types::prelude::*;
+use ckb_types::H256;
+use ckb_vm::Bytes;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::rpc_client::RpcClient;
+use crate::types::CellOutputWithData;
+
+/// A cell output together with its data, as seen by the VM.
+#[derive(Clone)]
+pub struct ResolvedCell {
+    pub output: CellOutput,
+    pub data: Bytes,
+}
+
+impl From<CellOutputWithData> for ResolvedCell {
+    fn from(cell: CellOutputWithData) -> Self {
+        Self {
+            output: cell.cell_output.into(),
+            data: cell.hex_data.map(|v| v.hex.into()).unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptGroupType {
+    Lock,
+    Type,
+}
+
+/// The inputs and outputs a script runs on, like the script groups of CKB's verifier.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScriptGroup {
+    pub script_hash: H256,
+    pub group_type: ScriptGroupType,
+    pub input_indices: Vec<usize>,
+    pub output_indices: Vec<usize>,
+}
+
+/// A transaction with the previous outputs of its inputs and its cell deps, dep groups
+/// expanded into their members like CKB does.
+#[derive(Clone)]
+pub struct ResolvedTransaction {
+    pub transaction: Transaction,
+    pub inputs: Vec<ResolvedCell>,
+    pub cell_deps: Vec<ResolvedCell>,
+    /// The group of the script being run, see [`ResolvedTransaction::set_group`].
+    pub group: Option<ScriptGroup>,
+}
+
+impl ResolvedTransaction {
+    /// Lock and type scripts of the inputs and outputs.
+    pub fn scripts(&self) -> Vec<Script> {
+        let outputs = self.transaction.raw().outputs().into_iter();
+        self.inputs
+            .iter()
+            .map(|cell| cell.output.clone())
+            .chain(outputs)
+            .flat_map(|output| output.type_().to_opt().into_iter().chain([output.lock()]))
+            .collect()
+    }
+
+    /// Groups the cells by `script` the way the verifier does: a script used as a type script
+    /// runs on the inputs and outputs of that type, otherwise it runs as a lock on the inputs
+    /// it locks.
+    pub fn set_group(&mut self, script: &Script) {
+        let script_hash = script.calc_script_hash();
+        let type_hash = |output: &CellOutput| {
+            output
+                .type_()
+                .to_opt()
+                .map(|type_| type_.calc_script_hash())
+        };
+        let outputs: Vec<CellOutput> = self.transaction.raw().outputs().into_iter().collect();
+
+        let type_inputs = indices(
+            self.inputs.iter().map(|cell| type_hash(&cell.output)),
+            &script_hash,
+        );
+        let type_outputs = indices(outputs.iter().map(type_hash), &script_hash);
+        let group = if !type_inputs.is_empty() || !type_outputs.is_empty() {
+            ScriptGroup {
+                script_hash: script_hash.unpack(),
+                group_type: ScriptGroupType::Type,
+                input_indices: type_inputs,
+                output_indices: type_outputs,
+            }
+        } else {
+            let lock_hashes = self
+                .inputs
+                .iter()
+                .map(|cell| Some(cell.output.lock().calc_script_hash()));
+            ScriptGroup {
+                script_hash: script_hash.unpack(),
+                group_type: ScriptGroupType::Lock,
+                input_indices: indices(lock_hashes, &script_hash),
+                output_indices: vec![],
+            }
+        };
+
+        self.group = Some(group);
+    }
+
+    pub fn output(&self, index: usize) -> Option<ResolvedCell> {
+        let raw = self.transaction.raw();
+        Some(ResolvedCell {
+            output: raw.outputs().get(index)?,
+            data: raw.outputs_data().get(index)?.raw_data(),
+        })
+    }
+}
+
+/// Resolves out points through the transactions that created them, so cells that have
+/// already been spent are resolved too. Transactions are cached since they never change.
+///
+/// Overridden cells take precedence over the chain, which lets callers preview cells a pending
+/// transaction is about to create.
+///
+/// Live cell and type script lookups are cached as well, so calls sharing a resolver see one
+/// consistent view of the chain.
+#[derive(Clone)]
+pub struct Resolver {
+    rpc: RpcClient,
+    transactions: Arc<Mutex<HashMap<H256, Transaction>>>,
+    live_cells: Arc<Mutex<HashMap<OutPoint, Option<ResolvedCell>>>>,
+    type_out_points: Arc<Mutex<HashMap<Script, Option<OutPoint>>>>,
+    overrides: Arc<Vec<(OutPoint, ResolvedCell)>>,
+    tip: Option<u64>,
+    allow_dead_cells: bool,
+}
+
+impl Resolver {
+    pub fn new(rpc: RpcClient) -> Self {
+        Self {
+            rpc,
+            transactions: Arc::new(Mutex::new(HashMap::new())),
+            live_cells: Arc::new(Mutex::new(HashMap::new())),
+            type_out_points: Arc::new(Mutex::new(HashMap::new())),
+            overrides: Arc::new(Vec::new()),
+            tip: None,
+            allow_dead_cells: false,
+        }
+    }
+
+    pub fn rpc(&self) -> &RpcClient {
+        &self.rpc
+    }
+
+    /// Lets [`Resolver::find_cell`] fall back to spent cells.
+    pub fn with_dead_cells(mut self, allow_dead_cells: bool) -> Self {
+        self.allow_dead_cells = allow_dead_cells;
+        self
+    }
+
+    /// Limits indexer lookups to cells created up to block `tip`.
+    pub fn with_tip(mut self, tip: u64) -> Self {
+        self.tip = Some(tip);
+        self
+    }
+
+    pub fn tip(&self) -> Option<u64> {
+        self.tip
+    }
+
+    pub fn with_overrides(mut self, overrides: Vec<(OutPoint, ResolvedCell)>) -> Self {
+        self.overrides = Arc::new(overrides);
+        self
+    }
+
+    pub fn overridden(&self, out_point: &OutPoint) -> Option<ResolvedCell> {
+        self.overrides
+            .iter()
+            .find(|(v, _)| v == out_point)
+            .map(|(_, cell)| cell.clone())
+    }
+
+    /// The first overridden cell whose type script is `script`.
+    pub fn find_overridden_by_type(&self, script: &Script) -> Option<OutPoint> {
+        self.overrides
+            .iter()
+            .find(|(_, cell)| cell.output.type_().to_opt().as_ref() == Some(script))
+            .map(|(out_point, _)| out_point.clone())
+    }
+
+    /// Looks up a live cell, `None` if the cell is not live.
+    pub async fn get_live_cell(&self, out_point: &OutPoint) -> Result<Option<ResolvedCell>, Error> {
+        if let Some(cell) = self.overridden(out_point) {
+            return Ok(Some(cell));
+        }
+        if let Some(cell) = self.live_cells.lock().unwrap().get(out_point) {
+            return Ok(cell.clone());
+        }
+
+        let cell = self
+            .rpc
+            .get_live_cell(&out_point.clone().into(), true)
+            .await?
+            .cell
+            .map(|cell| ResolvedCell {
+                output: cell.output.into(),
+                data: cell
+                    .data
+                    .map(|data| data.content.into_bytes())
+                    .unwrap_or_default(),
+            });
+        self.live_cells
+            .lock()
+            .unwrap()
+            .insert(out_point.clone(), cell.clone());
+        Ok(cell)
+    }
+
+    /// Looks up a live cell, or a spent one when dead cells are allowed. The flag tells whether
+    /// the cell is live.
+    pub async fn find_cell(
+        &self,
+        out_point: &OutPoint,
+    ) -> Result<Option<(ResolvedCell, bool)>, Error> {
+        if let Some(cell) = self.get_live_cell(out_point).await? {
+            return Ok(Some((cell, true)));
+        }
+        if !self.allow_dead_cells {
+            return Ok(None);
+        }
+        Ok(Some((self.resolve_cell(out_point).await?, false)))
+    }
+
+    /// Finds the out point of a live cell with type script `script`.
+    pub async fn find_out_point_by_type(&self, script: &Script) -> Result<Option<OutPoint>, Error> {
+        if let Some(out_point) = self.find_overridden_by_type(script) {
+            return Ok(Some(out_point));
+        }
+        if let Some(out_point) = self.type_out_points.lock().unwrap().get(script) {
+            return Ok(out_point.clone());
+        }
+
+        let mut query = CellQueryOptions::new_type(script.clone());
+        query.block_range = self.tip.map(|tip| ValueRangeOption::new(0, tip + 1));
+        let out_point = self
+            .rpc
+            .get_cells(query.into(), Order::Asc, 1, None)
+            .await?
+            .objects
+            .into_iter()
+            .next()
+            .map(|cell| cell.out_point.into());
+        self.type_out_points
+            .lock()
+            .unwrap()
+            .insert(script.clone(), out_point.clone());
+        Ok(out_point)
+    }
+
+    /// Fetches a transaction from the node, committed or pending.
+    pub async fn get_transaction(&self, tx_hash: &H256) -> Result<Transaction, Error> {
+        if let Some(tx) = self.transactions.lock().unwrap().get(tx_hash) {
+            return Ok(tx.clone());
+        }
+
+        let tx: Transaction = get_transaction(&self.rpc, tx_hash).await?.inner.into();
+        self.transactions
+            .lock()
+            .unwrap()
+            .insert(tx_hash.clone(), tx.clone());
+        Ok(tx)
+    }
+
+    pub async fn resolve_cell(&self, out_point: &OutPoint) -> Result<ResolvedCell, Error> {
+        if let Some(cell) = self.overridden(out_point) {
+            return Ok(cell);
+        }
+
+        let tx = self.get_transaction(&out_point.tx_hash().unpack()).await?;
+        let index: u32 = out_point.index().unpack();
+        let raw = tx.raw();
+        let output = raw
+            .outputs()
+            .get(index as usize)
+            .ok_or(Error::InvalidRequest("Out point index out of bound"))?;
+        let data = raw
+            .outputs_data()
+            .get(index as usize)
+            .ok_or(Error::InvalidRequest("Out point index out of bound"))?
+            .raw_data();
+
+        Ok(ResolvedCell { output, data })
+    }
+
+    /// Resolves a cell dep, expanding a dep group into the cells its data points to.
+    pub async fn resolve_cell_dep(&self, cell_dep: &CellDep) -> Result<Vec<ResolvedCell>, Error> {
+        let cell = self.resolve_cell(&cell_dep.out_point()).await?;
+        let dep_type = DepType::try_from(cell_dep.dep_type())
+            .map_err(|_| Error::Encoding("Invalid dep type"))?;
+        if dep_type == DepType::Code {
+            return Ok(vec![cell]);
+        }
+
+        let out_points = OutPointVec::from_slice(&cell.data)
+            .map_err(|_| Error::Encoding("Invalid dep group data"))?;
+        let mut cells = Vec::with_capacity(out_points.len());
+        for out_point in out_points {
+            cells.push(self.resolve_cell(&out_point).await?);
+        }
+        Ok(cells)
+    }
+
+    pub async fn resolve_transaction(
+        &self,
+        transaction: Transaction,
+    ) -> Result<ResolvedTransaction, Error> {
+        let raw = transaction.raw();
+
+        let mut inputs = Vec::new();
+        for input in raw.inputs() {
+            inputs.push(self.resolve_cell(&input.previous_output()).await?);
+        }
+
+        let mut cell_deps = Vec::new();
+        for cell_dep in raw.cell_deps() {
+            cell_deps.extend(self.resolve_cell_dep(&cell_dep).await?);
+        }
+
+        Ok(ResolvedTransaction {
+            transaction,
+            inputs,
+            cell_deps,
+            group: None,
+        })
+    }
+}
+
+fn indices(hashes: impl Iterator<Item = Option<Byte32>>, script_hash: &Byte32) -> Vec<usize> {
+    hashes
+        .enumerate()
+        .filter(|(_, hash)| hash.as_ref() == Some(script_hash))
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// Fetches a transaction from the node, committed or pending.
+pub async fn get_transaction(rpc: &RpcClient, tx_hash: &H256) -> Result<TransactionView, Error> {
+    let transaction = rpc
+        .get_transaction(tx_hash)
+        .await?
+        .and_then(|v| v.transaction)
+        .ok_or(Error::InvalidRequest("Transaction not found"))?;
+
+    match transaction.inner {
+        Either::Left(tx) => Ok(tx),
+        Either::Right(_) => Err(Error::Encoding("Unexpected hex encoded transaction")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ckb_types::bytes::Bytes;
+    use ckb_types::core::ScriptHashType;
+    use ckb_types::packed::RawTransaction;
+
+    use super::*;
+    use crate::mock_chain::MockChain;
+
+    fn out_point(index: u32) -> OutPoint {
+        OutPoint::new_builder()
+            .tx_hash([1; 32].pack())
+            .index(index.pack())
+            .build()
+    }
+
+    fn script(code_hash: u8) -> Script {
+        Script::new_builder()
+            .code_hash([code_hash; 32].pack())
+            .hash_type(ScriptHashType::Type.into())
+            .build()
+    }
+
+    fn cell(data: impl Into<Bytes>) -> ResolvedCell {
+        ResolvedCell {
+            output: CellOutput::new_builder().lock(script(0)).build(),
+            data: data.into(),
+        }
+    }
+
+    fn output(lock: u8, type_: Option<u8>) -> CellOutput {
+        CellOutput::new_builder()
+            .lock(script(lock))
+            .type_(type_.map(script).pack())
+            .build()
+    }
+
+    fn resolver(overrides: Vec<(OutPoint, ResolvedCell)>) -> Resolver {
+        let chain = MockChain {
+            headers: vec![],
+            cells: vec![],
+            transactions: vec![],
+        };
+        Resolver::new(RpcClient::mock(chain)).with_overrides(overrides)
+    }
+
+    fn cell_dep(index: u32, dep_type: DepType) -> CellDep {
+        CellDep::new_builder()
+            .out_point(out_point(index))
+            .dep_type(dep_type.into())
+            .build()
+    }
+
+    #[tokio::test]
+    async fn dep_groups_expand() {
+        let group = OutPointVec::new_builder()
+            .push(out_point(2))
+            .push(out_point(0))
+            .build();
+        let resolver = resolver(vec![
+            (out_point(0), cell(&b"first"[..])),
+            (out_point(1), cell(group.as_bytes())),
+            (out_point(2), cell(&b"second"[..])),
+            (out_point(3), cell(&b"not a group"[..])),
+        ]);
+        let data = |cells: Vec<ResolvedCell>| -> Vec<Bytes> {
+            cells.into_iter().map(|cell| cell.data).collect()
+        };
+
+        let code = resolver.resolve_cell_dep(&cell_dep(0, DepType::Code)).await;
+        assert_eq!(data(code.unwrap()), [&b"first"[..]]);
+
+        let members = resolver
+            .resolve_cell_dep(&cell_dep(1, DepType::DepGroup))
+            .await;
+        assert_eq!(data(members.unwrap()), [&b"second"[..], &b"first"[..]]);
+
+        let invalid = resolver
+            .resolve_cell_dep(&cell_dep(3, DepType::DepGroup))
+            .await;
+        assert!(matches!(invalid, Err(Error::Encoding(_))));
+    }
+
+    #[test]
+    fn groups_follow_the_verifier() {
+        let inputs = [output(1, Some(9)), output(2, None), output(1, None)];
+        let outputs = [output(2, Some(9)), output(1, None)];
+        let raw = RawTransaction::new_builder()
+            .outputs_data(vec![Bytes::new(); outputs.len()].pack())
+            .outputs(outputs.pack())
+            .build();
+        let mut tx = ResolvedTransaction {
+            transaction: Transaction::new_builder().raw(raw).build(),
+            inputs: inputs
+                .into_iter()
+                .map(|output| ResolvedCell {
+                    output,
+                    data: Bytes::new(),
+                })
+                .collect(),
+            cell_deps: vec![],
+            group: None,
+        };
+        let mut group = |script: Script| {
+            tx.set_group(&script);
+            let group = tx.group.clone().unwrap();
+            assert_eq!(group.script_hash, script.calc_script_hash().unpack());
+            (group.group_type, group.input_indices, group.output_indices)
+        };
+
+        assert_eq!(group(script(9)), (ScriptGroupType::Type, vec![0], vec![0]));
+        assert_eq!(
+            group(script(1)),
+            (ScriptGroupType::Lock, vec![0, 2], vec![])
+        );
+        assert_eq!(group(script(3)), (ScriptGroupType::Lock, vec![], vec![]));
+    }
+}
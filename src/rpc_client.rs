@@ -1,58 +1,57 @@
+use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-use ckb_jsonrpc_types::{CellWithStatus, JsonBytes, OutPoint, Uint32};
+use ckb_jsonrpc_types::{
+    CellWithStatus, HeaderView, JsonBytes, OutPoint, TransactionWithStatusResponse, Uint32,
+};
 use ckb_sdk::rpc::ckb_indexer::{Cell, Order, Pagination, SearchKey};
+use ckb_types::H256;
+use jsonrpc_core::futures::future::ready;
 use jsonrpc_core::futures::FutureExt;
-use jsonrpc_core::serde_json;
+use jsonrpc_core::serde_json::{self, Value};
 use reqwest::{Client, Url};
 
+use crate::bundle::{Exchange, Replay};
 use crate::error::Error;
+use crate::mock_chain::MockChain;
 
 pub type RpcResponse<T> = Pin<Box<dyn Future<Output = Result<T, Error>> + Send + 'static>>;
 
 macro_rules! jsonrpc {
     ($method:expr, $self:ident, $return:ty$(, $params:ident$(,)?)*) => {{
-        let data = format!(
-            r#"{{"id": {}, "jsonrpc": "2.0", "method": "{}", "params": {}}}"#,
-            $self.id.load(Ordering::Relaxed),
-            $method,
-            serde_json::to_value(($($params,)*)).unwrap()
-        );
-        $self.id.fetch_add(1, Ordering::Relaxed);
-
-        let req_json: serde_json::Value = serde_json::from_str(&data).unwrap();
-
-        let c = $self.raw.post($self.uri.clone()).json(&req_json);
-        async {
-            let resp = c
-                .send()
-                .await
-                .map_err::<Error, _>(|_| Error::JsonRpcRequestError)?;
-            let output = resp
-                .json::<jsonrpc_core::response::Output>()
-                .await
-                .map_err::<Error, _>(|_| Error::JsonRpcRequestError)?;
-
-            match output {
-                jsonrpc_core::response::Output::Success(success) => {
-                    Ok(serde_json::from_value::<$return>(success.result).unwrap())
-                }
-                jsonrpc_core::response::Output::Failure(_) => {
-                    Err(Error::JsonRpcRequestError)
-                }
-            }
-        }
+        let params = serde_json::to_value(($($params,)*)).unwrap();
+        $self.request($method, params).map(|result| {
+            result.and_then(|value| {
+                serde_json::from_value::<$return>(value)
+                    .map_err::<Error, _>(|_| Error::JsonRpcRequestError)
+            })
+        })
     }}
 }
 
+/// Where requests are answered: the CKB node, a [`MockChain`] or a replayed bundle.
+#[derive(Clone)]
+enum Backend {
+    Http {
+        raw: Client,
+        uri: Url,
+        id: Arc<AtomicU64>,
+    },
+    Mock(Arc<MockChain>),
+    Replay(Arc<Replay>),
+}
+
 #[derive(Clone)]
 pub struct RpcClient {
-    raw: Client,
-    uri: Url,
-    id: Arc<AtomicU64>,
+    backend: Backend,
+    /// Requests and results recorded since [`RpcClient::recording`].
+    exchanges: Option<Arc<Mutex<Vec<Exchange>>>>,
+    /// Data hashes of the cells seen by code hash searches, which never change for an out
+    /// point.
+    data_hashes: Arc<Mutex<HashMap<OutPoint, H256>>>,
 }
 
 impl RpcClient {
@@ -60,10 +59,107 @@ impl RpcClient {
         let uri = Url::parse(ckb_uri).expect("ckb uri, e.g. \"http://127.0.0.1:8114\"");
 
         RpcClient {
-            raw: Client::new(),
-            uri,
-            id: Arc::new(AtomicU64::new(0)),
+            backend: Backend::Http {
+                raw: Client::new(),
+                uri,
+                id: Arc::new(AtomicU64::new(0)),
+            },
+            exchanges: None,
+            data_hashes: Default::default(),
+        }
+    }
+
+    pub fn mock(chain: MockChain) -> Self {
+        RpcClient {
+            backend: Backend::Mock(Arc::new(chain)),
+            exchanges: None,
+            data_hashes: Default::default(),
+        }
+    }
+
+    pub fn replay(replay: Replay) -> Self {
+        RpcClient {
+            backend: Backend::Replay(Arc::new(replay)),
+            exchanges: None,
+            data_hashes: Default::default(),
+        }
+    }
+
+    /// A client on the same backend recording every request made through it and its clones.
+    /// It starts without cached data hashes, so the recording holds every cell a replay reads.
+    pub fn recording(&self) -> Self {
+        RpcClient {
+            backend: self.backend.clone(),
+            exchanges: Some(Arc::new(Mutex::new(Vec::new()))),
+            data_hashes: Default::default(),
+        }
+    }
+
+    pub fn cached_data_hash(&self, out_point: &OutPoint) -> Option<H256> {
+        self.data_hashes.lock().unwrap().get(out_point).cloned()
+    }
+
+    pub fn cache_data_hash(&self, out_point: OutPoint, data_hash: H256) {
+        self.data_hashes
+            .lock()
+            .unwrap()
+            .insert(out_point, data_hash);
+    }
+
+    pub fn exchanges(&self) -> Vec<Exchange> {
+        self.exchanges
+            .as_ref()
+            .map(|exchanges| exchanges.lock().unwrap().clone())
+            .unwrap_or_default()
+    }
+
+    fn request(&self, method: &'static str, params: Value) -> RpcResponse<Value> {
+        let response = match &self.backend {
+            Backend::Http { raw, uri, id } => {
+                let data = serde_json::json!({
+                    "id": id.fetch_add(1, Ordering::Relaxed),
+                    "jsonrpc": "2.0",
+                    "method": method,
+                    "params": params,
+                });
+                let c = raw.post(uri.clone()).json(&data);
+                async {
+                    let resp = c
+                        .send()
+                        .await
+                        .map_err::<Error, _>(|_| Error::JsonRpcRequestError)?;
+                    let output = resp
+                        .json::<jsonrpc_core::response::Output>()
+                        .await
+                        .map_err::<Error, _>(|_| Error::JsonRpcRequestError)?;
+
+                    match output {
+                        jsonrpc_core::response::Output::Success(success) => Ok(success.result),
+                        jsonrpc_core::response::Output::Failure(_) => {
+                            Err(Error::JsonRpcRequestError)
+                        }
+                    }
+                }
+                .boxed()
+            }
+            Backend::Mock(chain) => ready(chain.request(method, params.clone())).boxed(),
+            Backend::Replay(replay) => ready(replay.answer(method, &params)).boxed(),
+        };
+
+        let Some(exchanges) = self.exchanges.clone() else {
+            return response;
+        };
+        async move {
+            let result = response.await;
+            exchanges.lock().unwrap().push(Exchange {
+                method: method.to_string(),
+                params,
+                result: result.as_ref().cloned().unwrap_or_default(),
+                failed: result.is_err(),
+            });
+            result
         }
+        .boxed()
     }
 }
 
@@ -76,13 +172,30 @@ impl RpcClient {
         jsonrpc!("get_live_cell", self, CellWithStatus, out_point, with_data).boxed()
     }
 
+    pub fn get_tip_header(&self) -> RpcResponse<HeaderView> {
+        jsonrpc!("get_tip_header", self, HeaderView).boxed()
+    }
+
+    pub fn get_transaction(
+        &self,
+        tx_hash: &H256,
+    ) -> RpcResponse<Option<TransactionWithStatusResponse>> {
+        jsonrpc!(
+            "get_transaction",
+            self,
+            Option<TransactionWithStatusResponse>,
+            tx_hash
+        )
+        .boxed()
+    }
+
     pub fn get_cells(
         &self,
         search_key: SearchKey,
+        order: Order,
         limit: u32,
         cursor: Option<JsonBytes>,
     ) -> RpcResponse<Pagination<Cell>> {
-        let order = Order::Asc;
         let limit = Uint32::from(limit);
 
         jsonrpc!(
@@ -1,21 +1,34 @@
 // refer to https://github.com/nervosnetwork/ckb-vm/blob/develop/examples/ckb-vm-runner.rs
 
+use std::collections::HashMap;
+use std::future::Future;
 use std::sync::mpsc::channel;
 use std::sync::{Arc, Mutex};
 
 use ckb_hash::blake2b_256;
-use ckb_sdk::traits::CellQueryOptions;
-use ckb_types::core::Capacity;
-use ckb_types::packed::{CellOutput, OutPoint, Script, Transaction};
-use ckb_types::prelude::Entity;
+use ckb_types::core::{Capacity, ScriptHashType};
+use ckb_types::packed::{OutPoint, Script};
+use ckb_types::prelude::*;
 use ckb_vm::cost_model::estimate_cycles;
+use ckb_vm::decoder::build_decoder;
+use ckb_vm::instructions::Instruction;
 use ckb_vm::registers::{A0, A1, A2, A3, A4, A5, A7};
-use ckb_vm::{Bytes, Memory, Register, SupportMachine, Syscalls};
+use ckb_vm::{
+    Bytes, CoreMachine, DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, Memory,
+    Register, SparseMemory, SupportMachine, Syscalls, WXorXMemory,
+};
 use hex::encode;
+use serde::{Deserialize, Serialize};
 
+use crate::coverage::Coverage;
+use crate::elf;
 use crate::error::Error;
-use crate::rpc_client::RpcClient;
-use crate::types::CellOutputWithData;
+use crate::gdb::run_with_gdb;
+use crate::profile::Profiler;
+use crate::resolver::{ResolvedCell, ResolvedTransaction, Resolver};
+use crate::symbols::Symbols;
+use crate::trace::{Trace, TraceEntry, TRACE_BYTES_LIMIT};
+use crate::types::{Hex, PreparedCall};
 
 macro_rules! error {
     ($err:expr) => {{
@@ -30,19 +43,25 @@ macro_rules! error {
 
 macro_rules! output {
     ($machine:ident, $len_addr:ident, $bytes:expr, $addr:ident, $offset:expr, $len:ident) => {
+        // like CKB, reports and copies what's left of the bytes from the offset, the copy up
+        // to the buffer length
+        let begin = ($offset as usize).min($bytes.len());
         $machine
             .memory_mut()
-            .store64(&$len_addr, &($bytes.len() as u64))?;
-        if $len > 0 {
-            let begin = $offset as usize;
-            let end = ($offset + $len) as usize;
+            .store64(&$len_addr, &(($bytes.len() - begin) as u64))?;
+        let end = begin + ($bytes.len() - begin).min($len as usize);
+        if end > begin {
             $machine
                 .memory_mut()
                 .store_bytes($addr, &$bytes[begin..end])?;
         }
+        $machine.set_register(A0, SUCCESS);
     };
 }
 
+const SUCCESS: u64 = 0;
+const INDEX_OUT_OF_BOUND: u64 = 1;
+
 #[allow(unused)]
 #[repr(u64)]
 pub enum Source {
@@ -86,29 +105,175 @@ impl TryFrom<u64> for CellField {
     }
 }
 
+/// The ckb-vm version and ISA a script runs with on chain.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum VmVersion {
+    V0,
+    V1,
+    #[default]
+    V2,
+}
+
+impl VmVersion {
+    fn isa(self) -> u8 {
+        match self {
+            VmVersion::V0 => ckb_vm::ISA_IMC,
+            VmVersion::V1 => ckb_vm::ISA_IMC | ckb_vm::ISA_B | ckb_vm::ISA_MOP,
+            VmVersion::V2 => ckb_vm::ISA_IMC | ckb_vm::ISA_B | ckb_vm::ISA_MOP | ckb_vm::ISA_A,
+        }
+    }
+
+    fn version(self) -> u32 {
+        match self {
+            VmVersion::V0 => ckb_vm::machine::VERSION0,
+            VmVersion::V1 => ckb_vm::machine::VERSION1,
+            VmVersion::V2 => ckb_vm::machine::VERSION2,
+        }
+    }
+}
+
+/// The machine running scripts: ckb-vm's assembly machine, or its interpreter which runs on
+/// any host but slower.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum VmBackend {
+    #[cfg(feature = "asm")]
+    Asm,
+    Interpreter,
+}
+
+impl Default for VmBackend {
+    fn default() -> Self {
+        #[cfg(feature = "asm")]
+        return VmBackend::Asm;
+        #[cfg(not(feature = "asm"))]
+        return VmBackend::Interpreter;
+    }
+}
+
+/// Caps on what an execution may use, to guard the server against pathological scripts.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Size of the code ELF, in bytes.
+    pub code_size: usize,
+    /// Memory of the VM, in bytes, of which the top quarter is the stack.
+    pub memory: usize,
+    /// Size of the content passed to `set_content`, in bytes.
+    pub content_size: usize,
+    /// Size of all `debug` output of an execution, in bytes.
+    pub debug_size: usize,
+}
+
+impl From<ScriptHashType> for VmVersion {
+    fn from(hash_type: ScriptHashType) -> Self {
+        match hash_type {
+            ScriptHashType::Data => VmVersion::V0,
+            ScriptHashType::Data1 => VmVersion::V1,
+            // `type` scripts run on the latest VM since the hardfork
+            ScriptHashType::Data2 | ScriptHashType::Type => VmVersion::V2,
+        }
+    }
+}
+
+/// The data hash type running code on the VM, for scripts made up to run code-level calls.
+impl From<VmVersion> for ScriptHashType {
+    fn from(version: VmVersion) -> Self {
+        match version {
+            VmVersion::V0 => ScriptHashType::Data,
+            VmVersion::V1 => ScriptHashType::Data1,
+            VmVersion::V2 => ScriptHashType::Data2,
+        }
+    }
+}
+
+impl TryFrom<u8> for VmVersion {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(VmVersion::V0),
+            1 => Ok(VmVersion::V1),
+            2 => Ok(VmVersion::V2),
+            _ => Err(format!("Invalid vm version {}", value)),
+        }
+    }
+}
+
+impl From<VmVersion> for u8 {
+    fn from(version: VmVersion) -> Self {
+        match version {
+            VmVersion::V0 => 0,
+            VmVersion::V1 => 1,
+            VmVersion::V2 => 2,
+        }
+    }
+}
+
 #[derive(Clone)]
 struct Context {
     content: Arc<Mutex<Option<Bytes>>>,
-    rpc: RpcClient,
+    resolver: Resolver,
     script: Option<Script>,
-    cell: Option<CellOutputWithData>,
-    // wait for second phase to do with ckb transaction
-    _tx: Option<Transaction>,
+    cell: Option<ResolvedCell>,
+    tx: Option<ResolvedTransaction>,
+    trace: Option<Arc<Mutex<Trace>>>,
+    limits: Limits,
+    /// Bytes of `debug` output so far.
+    debug_size: usize,
+    /// The limit a syscall exceeded, which stopped the script.
+    exceeded: Arc<Mutex<Option<Error>>>,
 }
 
 impl Context {
-    pub fn new(
-        rpc: RpcClient,
-        script: Option<Script>,
-        cell: Option<CellOutputWithData>,
-        tx: Option<Transaction>,
-    ) -> Self {
+    pub fn new(call: PreparedCall) -> Self {
         Self {
             content: Arc::new(Mutex::new(None)),
-            rpc,
-            script,
-            cell,
-            _tx: tx,
+            resolver: call.resolver,
+            script: call.script,
+            cell: call.cell.map(Into::into),
+            tx: call.tx,
+            trace: call
+                .trace_limit
+                .map(|limit| Arc::new(Mutex::new(Trace::new(limit)))),
+            limits: call.limits,
+            debug_size: 0,
+            exceeded: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Looks up the cell a syscall addresses, `None` when `index` is out of bound.
+    fn cell_by_source(
+        &self,
+        index: u64,
+        source: u64,
+    ) -> Result<Option<ResolvedCell>, ckb_vm::error::Error> {
+        let index = index as usize;
+        if let Some((tx, group)) = self
+            .tx
+            .as_ref()
+            .and_then(|tx| Some((tx, tx.group.as_ref()?)))
+        {
+            if source == Source::GroupInput as u64 {
+                let input = group.input_indices.get(index);
+                return Ok(input.and_then(|&i| tx.inputs.get(i).cloned()));
+            }
+            if source == Source::GroupOutput as u64 {
+                let output = group.output_indices.get(index);
+                return Ok(output.and_then(|&i| tx.output(i)));
+            }
+        }
+
+        match (&self.cell, &self.tx) {
+            (Some(cell), _) if source == Source::GroupInput as u64 => {
+                Ok((index == 0).then(|| cell.clone()))
+            }
+            (_, Some(tx)) if source == Source::Input as u64 => Ok(tx.inputs.get(index).cloned()),
+            (_, Some(tx)) if source == Source::Output as u64 => Ok(tx.output(index)),
+            (_, Some(tx)) if source == Source::CellDep as u64 => {
+                Ok(tx.cell_deps.get(index).cloned())
+            }
+            (None, None) => Err(error!("Cell is missing")),
+            _ => Err(error!("Invalid index or source")),
         }
     }
 }
@@ -148,7 +313,6 @@ impl Context {
         &self,
         machine: &mut impl SupportMachine<REG = u64>,
     ) -> Result<(), ckb_vm::error::Error> {
-        let cell = self.cell.clone().ok_or(error!("Cell is missing"))?;
         let addr = machine.registers()[A0].to_u64();
         let len_addr = machine.registers()[A1];
         let len = machine.memory_mut().load64(&len_addr)?;
@@ -156,11 +320,12 @@ impl Context {
         let index = machine.registers()[A3];
         let source = machine.registers()[A4];
 
-        if index != 0 || source != Source::GroupInput as u64 {
-            return Err(error!("Invalid index or source"));
-        }
+        let Some(cell) = self.cell_by_source(index, source)? else {
+            machine.set_register(A0, INDEX_OUT_OF_BOUND);
+            return Ok(());
+        };
 
-        let bytes = CellOutput::from(cell.cell_output).as_slice().to_vec();
+        let bytes = cell.output.as_slice().to_vec();
         output!(machine, len_addr, bytes, addr, offset, len);
         Ok(())
     }
@@ -169,7 +334,6 @@ impl Context {
         &self,
         machine: &mut impl SupportMachine<REG = u64>,
     ) -> Result<(), ckb_vm::error::Error> {
-        let cell = self.cell.clone().ok_or(error!("Cell is missing"))?;
         let addr = machine.registers()[A0].to_u64();
         let len_addr = machine.registers()[A1];
         let len = machine.memory_mut().load64(&len_addr)?;
@@ -177,11 +341,12 @@ impl Context {
         let index = machine.registers()[A3];
         let source = machine.registers()[A4];
 
-        if index != 0 || source != Source::GroupInput as u64 {
-            return Err(error!("Invalid index or source"));
-        }
+        let Some(cell) = self.cell_by_source(index, source)? else {
+            machine.set_register(A0, INDEX_OUT_OF_BOUND);
+            return Ok(());
+        };
 
-        let bytes = cell.hex_data.ok_or(error!("Cell data is missing"))?.hex;
+        let bytes = cell.data;
         output!(machine, len_addr, bytes, addr, offset, len);
         Ok(())
     }
@@ -190,7 +355,6 @@ impl Context {
         &self,
         machine: &mut impl SupportMachine<REG = u64>,
     ) -> Result<(), ckb_vm::error::Error> {
-        let cell = self.cell.clone().ok_or(error!("Cell is missing"))?;
         let addr = machine.registers()[A0].to_u64();
         let len_addr = machine.registers()[A1];
         let len = machine.memory_mut().load64(&len_addr)?;
@@ -199,46 +363,33 @@ impl Context {
         let source = machine.registers()[A4];
         let field = machine.registers()[A5];
 
-        if index != 0 || source != Source::GroupInput as u64 {
-            return Err(error!("Invalid index or source"));
-        }
+        let Some(cell) = self.cell_by_source(index, source)? else {
+            machine.set_register(A0, INDEX_OUT_OF_BOUND);
+            return Ok(());
+        };
 
+        let output = cell.output;
         let bytes = match field.try_into()? {
-            CellField::DataHash => blake2b_256(
-                hex::decode(cell.hex_data.ok_or(error!("Cell data is missing"))?.hex)
-                    .map_err(|_| error!("Invalid hexed cell data "))?,
-            )
-            .to_vec(),
-            CellField::Lock => Script::from(cell.cell_output.lock).as_slice().to_vec(),
-            CellField::Type => cell
-                .cell_output
-                .type_
-                .map(|type_| Script::from(type_).as_slice().to_vec())
+            CellField::DataHash => blake2b_256(&cell.data).to_vec(),
+            CellField::Lock => output.lock().as_slice().to_vec(),
+            CellField::Type => output
+                .type_()
+                .to_opt()
+                .map(|type_| type_.as_slice().to_vec())
                 .unwrap_or_default(),
-            CellField::LockHash => Script::from(cell.cell_output.lock)
-                .calc_script_hash()
-                .raw_data()
-                .to_vec(),
-            CellField::TypeHash => cell
-                .cell_output
-                .type_
-                .map(|type_| Script::from(type_).calc_script_hash().raw_data().to_vec())
+            CellField::LockHash => output.lock().calc_script_hash().raw_data().to_vec(),
+            CellField::TypeHash => output
+                .type_()
+                .to_opt()
+                .map(|type_| type_.calc_script_hash().raw_data().to_vec())
                 .unwrap_or_default(),
-            CellField::Capacity => u64::from(cell.cell_output.capacity).to_le_bytes().to_vec(),
-            CellField::OccupiedCapacity => {
-                let data_len = cell
-                    .hex_data
-                    .ok_or(error!("Cell data is missing"))?
-                    .hex
-                    .len()
-                    / 2;
-                CellOutput::from(cell.cell_output)
-                    .occupied_capacity(Capacity::bytes(data_len).unwrap())
-                    .unwrap()
-                    .as_u64()
-                    .to_le_bytes()
-                    .to_vec()
-            }
+            CellField::Capacity => output.capacity().raw_data().to_vec(),
+            CellField::OccupiedCapacity => output
+                .occupied_capacity(Capacity::bytes(cell.data.len()).unwrap())
+                .unwrap()
+                .as_u64()
+                .to_le_bytes()
+                .to_vec(),
         };
 
         output!(machine, len_addr, bytes, addr, offset, len);
@@ -255,25 +406,17 @@ impl Context {
         let script_addr = machine.registers()[A2];
         let script_len = machine.registers()[A3];
 
-        let script = Script::from_slice(&machine.memory_mut().load_bytes(script_addr, script_len)?)
+        let script = Script::from_slice(&load_bytes(machine, script_addr, script_len)?)
             .map_err(|_| error!("Invalid type script"))?;
 
-        let rpc = self.rpc.clone();
-        let (tx, rx) = channel();
-        std::thread::spawn(move || {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            let cell = rt
-                .block_on(rpc.get_cells(CellQueryOptions::new_type(script).into(), 1, None))
-                .map_err(|err| error!(err))
-                .map(|v| v.objects.into_iter().next());
-            tx.send(cell).unwrap();
-        });
-
-        let Some(cell) = rx.recv().unwrap()? else {
+        let resolver = self.resolver.clone();
+        let Some(out_point) =
+            block_on(async move { resolver.find_out_point_by_type(&script).await })
+                .map_err(|err| error!(err))?
+        else {
             return Err(error!("Cell not found"));
         };
 
-        let out_point = OutPoint::from(cell.out_point);
         output!(machine, len_addr, out_point.as_slice(), addr, 0, len);
         Ok(())
     }
@@ -287,31 +430,19 @@ impl Context {
         let len = machine.memory_mut().load64(&len_addr)?;
         let outpoint_addr = machine.registers()[A2];
 
-        let out_point = OutPoint::from_slice(
-            &machine
-                .memory_mut()
-                .load_bytes(outpoint_addr, OutPoint::TOTAL_SIZE as u64)?,
-        )
+        let out_point = OutPoint::from_slice(&load_bytes(
+            machine,
+            outpoint_addr,
+            OutPoint::TOTAL_SIZE as u64,
+        )?)
         .map_err(|_| error!("Invalid type script"))?;
 
-        let rpc = self.rpc.clone();
-        let (tx, rx) = channel();
-        std::thread::spawn(move || {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            let cell = rt
-                .block_on(rpc.get_live_cell(&out_point.into(), false))
-                .map_err(|err| error!(err))
-                .map(|v| v.cell);
-            tx.send(cell).unwrap();
-        });
+        let resolver = self.resolver.clone();
+        let (cell, _) = block_on(async move { resolver.find_cell(&out_point).await })
+            .map_err(|err| error!(err))?
+            .ok_or(error!("Cell not found"))?;
 
-        let cell: CellOutput = rx
-            .recv()
-            .unwrap()?
-            .ok_or(error!("Cell not found"))?
-            .output
-            .into();
-        output!(machine, len_addr, cell.as_slice(), addr, 0, len);
+        output!(machine, len_addr, cell.output.as_slice(), addr, 0, len);
         Ok(())
     }
 
@@ -324,42 +455,55 @@ impl Context {
         let len = machine.memory_mut().load64(&len_addr)?;
         let outpoint_addr = machine.registers()[A2];
 
-        let out_point = OutPoint::from_slice(
-            &machine
-                .memory_mut()
-                .load_bytes(outpoint_addr, OutPoint::TOTAL_SIZE as u64)?,
-        )
+        let out_point = OutPoint::from_slice(&load_bytes(
+            machine,
+            outpoint_addr,
+            OutPoint::TOTAL_SIZE as u64,
+        )?)
         .map_err(|_| error!("Invalid type script"))?;
 
-        let rpc = self.rpc.clone();
-        let (tx, rx) = channel();
-        std::thread::spawn(move || {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            let cell = rt
-                .block_on(rpc.get_live_cell(&out_point.into(), true))
-                .map_err(|err| error!(err))
-                .map(|v| v.cell);
-            tx.send(cell).unwrap();
-        });
-
-        let data = rx
-            .recv()
-            .unwrap()?
-            .ok_or(error!("Cell not found"))?
-            .data
-            .unwrap();
+        let resolver = self.resolver.clone();
+        let (cell, _) = block_on(async move { resolver.find_cell(&out_point).await })
+            .map_err(|err| error!(err))?
+            .ok_or(error!("Cell not found"))?;
 
-        output!(machine, len_addr, data.content.as_bytes(), addr, 0, len);
+        output!(machine, len_addr, cell.data, addr, 0, len);
         Ok(())
     }
 }
 
+/// Syscalls are synchronous, so node requests run to completion on a separate runtime.
+fn block_on<F>(future: F) -> F::Output
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (tx, rx) = channel();
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        tx.send(rt.block_on(future)).unwrap();
+    });
+    rx.recv().unwrap()
+}
+
 impl<M: SupportMachine<REG = u64>> Syscalls<M> for Context {
     fn initialize(&mut self, _machine: &mut M) -> Result<(), ckb_vm::error::Error> {
         Ok(())
     }
 
     fn ecall(&mut self, machine: &mut M) -> Result<bool, ckb_vm::error::Error> {
+        match self.trace.clone() {
+            Some(trace) => self.traced_syscall(machine, &trace),
+            None => self.syscall(machine),
+        }
+    }
+}
+
+impl Context {
+    fn syscall(
+        &mut self,
+        machine: &mut impl SupportMachine<REG = u64>,
+    ) -> Result<bool, ckb_vm::error::Error> {
         match machine.registers()[A7].to_u64() {
             // version - code
             2041 => machine.set_register(A0, u64::MAX),
@@ -386,68 +530,677 @@ impl<M: SupportMachine<REG = u64>> Syscalls<M> for Context {
                 let addr = machine.registers()[A0].to_u64();
                 let len = machine.registers()[A1];
                 let len = machine.memory_mut().load64(&len)?;
+                if len > self.limits.content_size as u64 {
+                    return Err(self.exceed(Error::ContentTooLarge(self.limits.content_size)));
+                }
 
-                *self.content.clone().lock().unwrap() =
-                    Some(machine.memory_mut().load_bytes(addr, len)?);
+                *self.content.clone().lock().unwrap() = Some(load_bytes(machine, addr, len)?);
             }
             // debug - code
             2177 => {
-                let mut addr = machine.registers()[A0];
-                let mut buffer = Vec::new();
-
-                loop {
-                    let byte = machine.memory_mut().load8(&addr)?.to_u8();
-                    if byte == 0 {
-                        break;
-                    }
-                    buffer.push(byte);
-                    addr += 1;
+                let remaining = self.limits.debug_size - self.debug_size;
+                let buffer = load_c_string(machine, machine.registers()[A0], remaining + 1)?;
+                if buffer.len() > remaining {
+                    return Err(self.exceed(Error::DebugOutputTooLarge(self.limits.debug_size)));
                 }
-
-                println!("{}", String::from_utf8(buffer).unwrap());
+                self.debug_size += buffer.len();
+                println!("{}", String::from_utf8_lossy(&buffer));
             }
             _ => return Ok(false),
         };
 
         Ok(true)
     }
+
+    /// Keeps the exceeded limit to report it in place of the VM error stopping the script.
+    fn exceed(&self, error: Error) -> ckb_vm::error::Error {
+        let vm_error = error!(error);
+        *self.exceeded.lock().unwrap() = Some(error);
+        vm_error
+    }
+
+    /// Runs the syscall and records it with its decoded arguments and what it returned.
+    fn traced_syscall(
+        &mut self,
+        machine: &mut impl SupportMachine<REG = u64>,
+        trace: &Mutex<Trace>,
+    ) -> Result<bool, ckb_vm::error::Error> {
+        let registers = machine.registers();
+        let number = registers[A7].to_u64();
+        let (addr, len_addr, offset) = (registers[A0], registers[A1], registers[A2]);
+        let (a3, a4, a5) = (registers[A3], registers[A4], registers[A5]);
+
+        let mut entry = TraceEntry {
+            syscall: number,
+            name: syscall_name(number).to_string(),
+            source: None,
+            index: None,
+            field: None,
+            out_point: None,
+            script: None,
+            bytes: None,
+            len: None,
+            return_code: None,
+            error: None,
+            cycles: 0,
+        };
+        match number {
+            2071 | 2091 | 2081 => {
+                entry.index = Some(a3);
+                entry.source = Some(source_name(a4));
+                if number == 2081 {
+                    entry.field = Some(field_name(a5));
+                }
+            }
+            2277 => {
+                entry.script = load_bytes(machine, offset, a3)
+                    .ok()
+                    .and_then(|bytes| Script::from_slice(&bytes).ok())
+                    .map(Into::into);
+            }
+            2287 | 2297 => {
+                entry.out_point = load_bytes(machine, offset, OutPoint::TOTAL_SIZE as u64)
+                    .ok()
+                    .and_then(|bytes| OutPoint::from_slice(&bytes).ok())
+                    .map(Into::into);
+            }
+            2103 => {
+                entry.bytes = machine
+                    .memory_mut()
+                    .load64(&len_addr)
+                    .and_then(|len| load_bytes(machine, addr, len))
+                    .ok()
+                    .map(truncated);
+            }
+            2177 => {
+                entry.bytes = load_c_string(machine, addr, TRACE_BYTES_LIMIT)
+                    .ok()
+                    .map(|v| truncated(v.into()))
+            }
+            _ => {}
+        }
+
+        // syscalls loading data report the length left from their offset at `len_addr`, and
+        // write up to the length found there before
+        let loads = matches!(
+            number,
+            2052 | 2061 | 2071 | 2091 | 2081 | 2277 | 2287 | 2297
+        );
+        let requested = if loads {
+            machine.memory_mut().load64(&len_addr).ok()
+        } else {
+            None
+        };
+
+        let result = self.syscall(machine);
+        match &result {
+            // set_content and debug leave A0 untouched
+            Ok(true) if matches!(number, 2103 | 2177) => {}
+            Ok(true) => {
+                let return_code = machine.registers()[A0];
+                entry.return_code = Some(return_code);
+                if let (Some(requested), SUCCESS) = (requested, return_code) {
+                    let len = machine.memory_mut().load64(&len_addr).ok();
+                    let written = requested.min(len.unwrap_or_default());
+                    entry.len = len;
+                    entry.bytes = load_bytes(machine, addr, written.min(TRACE_BYTES_LIMIT as u64))
+                        .ok()
+                        .map(Into::into);
+                }
+            }
+            Ok(false) => entry.error = Some("Unknown syscall".to_string()),
+            Err(err) => entry.error = Some(err.to_string()),
+        }
+        entry.cycles = machine.cycles();
+
+        trace.lock().unwrap().push(entry);
+        result
+    }
 }
 
-pub fn execute_riscv_binary(
-    rpc: RpcClient,
-    code: Bytes,
-    args: Vec<Bytes>,
-    script: Option<Script>,
-    cell: Option<CellOutputWithData>,
-    tx: Option<Transaction>,
-) -> Result<Option<Bytes>, Error> {
-    let context = Context::new(rpc, script, cell, tx);
-
-    let asm_core = ckb_vm::machine::asm::AsmCoreMachine::new(
-        ckb_vm::ISA_IMC | ckb_vm::ISA_B | ckb_vm::ISA_MOP | ckb_vm::ISA_A,
-        ckb_vm::machine::VERSION2,
-        u64::MAX,
-    );
-    let core = ckb_vm::DefaultMachineBuilder::new(asm_core)
-        .instruction_cycle_func(Box::new(estimate_cycles))
-        .syscall(Box::new(context.clone()))
-        .build();
-    let mut machine = ckb_vm::machine::asm::AsmMachine::new(core);
-
-    let args = args
-        .into_iter()
+fn syscall_name(number: u64) -> &'static str {
+    match number {
+        2041 => "version",
+        2052 => "load_script",
+        2061 => "load_script_hash",
+        2071 => "load_cell",
+        2091 => "load_cell_data",
+        2081 => "load_cell_by_field",
+        2277 => "find_out_point_by_type",
+        2287 => "find_cell_by_out_point",
+        2297 => "find_cell_data_by_out_point",
+        2103 => "set_content",
+        2177 => "debug",
+        _ => "unknown",
+    }
+}
+
+fn source_name(source: u64) -> String {
+    match source {
+        1 => "input".to_string(),
+        2 => "output".to_string(),
+        3 => "cell_dep".to_string(),
+        4 => "header_dep".to_string(),
+        v if v == Source::GroupInput as u64 => "group_input".to_string(),
+        v if v == Source::GroupOutput as u64 => "group_output".to_string(),
+        v => format!("{v:#x}"),
+    }
+}
+
+fn field_name(field: u64) -> String {
+    match CellField::try_from(field) {
+        Ok(CellField::Capacity) => "capacity".to_string(),
+        Ok(CellField::DataHash) => "data_hash".to_string(),
+        Ok(CellField::Lock) => "lock".to_string(),
+        Ok(CellField::LockHash) => "lock_hash".to_string(),
+        Ok(CellField::Type) => "type".to_string(),
+        Ok(CellField::TypeHash) => "type_hash".to_string(),
+        Ok(CellField::OccupiedCapacity) => "occupied_capacity".to_string(),
+        Err(_) => field.to_string(),
+    }
+}
+
+fn truncated(bytes: Bytes) -> Hex {
+    bytes.slice(..bytes.len().min(TRACE_BYTES_LIMIT)).into()
+}
+
+/// Loads `len` bytes at `addr`. The asm machine only bounds them by `RISCV_MAX_MEMORY`, and
+/// reads past its allocation when the configured memory is smaller.
+fn load_bytes(
+    machine: &mut impl SupportMachine<REG = u64>,
+    addr: u64,
+    len: u64,
+) -> Result<Bytes, ckb_vm::error::Error> {
+    match addr.checked_add(len) {
+        Some(end) if end <= machine.memory().memory_size() as u64 => {
+            machine.memory_mut().load_bytes(addr, len)
+        }
+        _ => Err(ckb_vm::error::Error::MemOutOfBound),
+    }
+}
+
+/// Loads the NUL-terminated string at `addr`, or its first `max_len` bytes.
+fn load_c_string(
+    machine: &mut impl SupportMachine<REG = u64>,
+    mut addr: u64,
+    max_len: usize,
+) -> Result<Vec<u8>, ckb_vm::error::Error> {
+    let mut buffer = Vec::new();
+    while buffer.len() < max_len {
+        let byte = machine.memory_mut().load8(&addr)?.to_u8();
+        if byte == 0 {
+            return Ok(buffer);
+        }
+        buffer.push(byte);
+        addr += 1;
+    }
+    Ok(buffer)
+}
+
+/// SSRI method path: the first 8 bytes of the ckb hash of e.g. `UDT.name`.
+pub fn method_path(name: &str) -> Bytes {
+    Bytes::copy_from_slice(&blake2b_256(name)[..8])
+}
+
+/// What an execution produced.
+pub struct Execution {
+    pub content: Option<Bytes>,
+    pub trace: Option<Trace>,
+    /// Folded stacks of the cycles spent, when profiling.
+    pub profile: Option<String>,
+    /// lcov tracefile of the lines executed, when covering.
+    pub coverage: Option<String>,
+    pub cycles: u64,
+}
+
+/// Runs the code with the call's context. Failures carry the symbolized `backtrace` of where
+/// the script stopped in the error data, and the trace, profile and coverage when enabled.
+///
+/// Calls run on the configured backend, except profiled, covered and debugged calls, which run
+/// on the interpreter one instruction at a time. Covered calls add their coverage to the call's.
+///
+/// Code over the call's size limit, or that isn't a RISC-V 64 ELF, is rejected before loading.
+pub fn execute_riscv_binary(code: Bytes, call: PreparedCall) -> Result<Execution, Error> {
+    let limits = call.limits;
+    if code.len() > limits.code_size {
+        return Err(Error::CodeTooLarge {
+            size: code.len(),
+            limit: limits.code_size,
+        });
+    }
+    elf::validate(&code)?;
+    let vm_version = call.vm_version;
+    let args = call
+        .args
+        .iter()
         .map(|arg| Bytes::copy_from_slice(encode(arg).as_bytes()))
         .collect::<Vec<Bytes>>();
-    machine
-        .load_program(&code, &args)
-        .map_err(|err| Error::Vm(format!("Failed to load program: {err}")))?;
-    let error_code = machine
-        .run()
-        .map_err(|err| Error::Vm(format!("Failed to run program: {err}")))?;
+    let mut profiler = if call.profile {
+        let symbols = Symbols::parse(&code)
+            .ok_or_else(|| Error::Vm("Failed to load program: not an ELF".to_string()))?;
+        Some(Profiler::new(symbols))
+    } else {
+        None
+    };
+    let session_coverage = call.coverage.clone();
+    let mut executed = session_coverage
+        .as_ref()
+        .map(|_| HashMap::<u64, u64>::new());
+    let gdb = call.gdb.clone();
+    let backend = call.backend;
+    let context = Context::new(call);
+
+    let trace = || {
+        context
+            .trace
+            .as_ref()
+            .map(|trace| trace.lock().unwrap().clone())
+    };
+    let limited_memory = limits.memory < ckb_vm::RISCV_MAX_MEMORY;
+    let load_failed = |err| match err {
+        ckb_vm::Error::MemOutOfBound if limited_memory => Error::MemoryExceeded(limits.memory),
+        err => Error::Vm(format!("Failed to load program: {err}")),
+    };
+
+    let interpreter = || {
+        let core = DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new_with_memory(
+            vm_version.isa(),
+            vm_version.version(),
+            u64::MAX,
+            limits.memory,
+        );
+        DefaultMachineBuilder::new(core)
+            .instruction_cycle_func(Box::new(estimate_cycles))
+            .syscall(Box::new(context.clone()))
+            .build()
+    };
+
+    let stopped = match &gdb {
+        Some(addr) => {
+            let mut machine = interpreter();
+            machine.load_program(&code, &args).map_err(load_failed)?;
+            run_with_gdb(machine, addr)
+        }
+        None if profiler.is_some() || executed.is_some() => {
+            let mut machine = interpreter();
+            machine.load_program(&code, &args).map_err(load_failed)?;
+            let result = run_stepped(&mut machine, |pc, instruction, cycles, next_pc| {
+                if let Some(profiler) = &mut profiler {
+                    profiler.step(pc, instruction, cycles, next_pc);
+                }
+                if let Some(executed) = &mut executed {
+                    *executed.entry(pc).or_default() += 1;
+                }
+            });
+            Stopped::new(result, &machine)
+        }
+        None => match backend {
+            #[cfg(feature = "asm")]
+            VmBackend::Asm => {
+                let asm_core = ckb_vm::machine::asm::AsmCoreMachine::new_with_memory(
+                    vm_version.isa(),
+                    vm_version.version(),
+                    u64::MAX,
+                    limits.memory,
+                );
+                let core = DefaultMachineBuilder::new(asm_core)
+                    .instruction_cycle_func(Box::new(estimate_cycles))
+                    .syscall(Box::new(context.clone()))
+                    .build();
+                let mut machine = ckb_vm::machine::asm::AsmMachine::new(core);
+                machine.load_program(&code, &args).map_err(load_failed)?;
+                let result = machine.run();
+                Stopped::new(result, &machine.machine)
+            }
+            VmBackend::Interpreter => {
+                let mut machine = interpreter();
+                machine.load_program(&code, &args).map_err(load_failed)?;
+                let result = machine.run();
+                Stopped::new(result, &machine)
+            }
+        },
+    };
+
+    let coverage = match (session_coverage, executed) {
+        (Some(session), Some(executed)) => {
+            let coverage = Symbols::parse(&code)
+                .map(|symbols| Coverage::new(blake2b_256(&code).into(), &symbols, &executed))
+                .unwrap_or_default();
+            session.lock().unwrap().merge(&coverage);
+            Some(coverage.lcov())
+        }
+        _ => None,
+    };
+
+    let failed = |error: Error, pc: Option<u64>| {
+        let error = match (Symbols::parse(&code), pc) {
+            (Some(symbols), Some(pc)) => error.with_data("backtrace", symbols.backtrace(pc)),
+            _ => error,
+        };
+        let error = match &profiler {
+            Some(profiler) => error.with_data("profile", profiler.folded()),
+            None => error,
+        };
+        let error = match &coverage {
+            Some(coverage) => error.with_data("coverage", coverage),
+            None => error,
+        };
+        match trace() {
+            Some(trace) => error.with_data("trace", trace),
+            None => error,
+        }
+    };
+    let error_code = match stopped.result {
+        Ok(error_code) => error_code,
+        Err(err) => {
+            // the asm machine only updates the pc at the start of basic blocks
+            let pc = match err {
+                ckb_vm::Error::InvalidInstruction { pc, .. } => Some(pc),
+                _ => stopped.pc,
+            };
+            let exceeded = context.exceeded.lock().unwrap().take();
+            let error = match (exceeded, err) {
+                (Some(error), _) => error,
+                (None, ckb_vm::Error::MemOutOfBound | ckb_vm::Error::MemOutOfStack)
+                    if limited_memory =>
+                {
+                    Error::MemoryExceeded(limits.memory)
+                }
+                (None, err) => Error::Vm(format!("Failed to run program: {err}")),
+            };
+            return Err(failed(error, pc));
+        }
+    };
     if error_code != 0 {
-        return Err(Error::Script(error_code));
+        // the pc is past the exit ecall
+        return Err(failed(
+            Error::Script(error_code),
+            stopped.pc.map(|pc| pc.wrapping_sub(4)),
+        ));
     }
 
-    let result = context.content.lock().unwrap().clone();
-    Ok(result)
+    let content = context.content.lock().unwrap().clone();
+    Ok(Execution {
+        content,
+        trace: trace(),
+        profile: profiler.map(|profiler| profiler.folded()),
+        coverage,
+        cycles: stopped.cycles,
+    })
+}
+
+/// How a machine stopped.
+pub struct Stopped {
+    pub result: Result<i8, ckb_vm::Error>,
+    /// Unknown when the machine ran out of sight, e.g. after GDB detached.
+    pub pc: Option<u64>,
+    pub cycles: u64,
+}
+
+impl Stopped {
+    pub fn new(
+        result: Result<i8, ckb_vm::Error>,
+        machine: &impl SupportMachine<REG = u64>,
+    ) -> Self {
+        Self {
+            result,
+            pc: Some(*machine.pc()),
+            cycles: machine.cycles(),
+        }
+    }
+}
+
+/// Runs the machine one instruction at a time like [`DefaultMachine::run`], passing each
+/// instruction's pc, cycles and the pc it moved on to to `on_step`.
+fn run_stepped<Inner: SupportMachine<REG = u64>>(
+    machine: &mut DefaultMachine<Inner>,
+    mut on_step: impl FnMut(u64, Instruction, u64, u64),
+) -> Result<i8, ckb_vm::Error> {
+    let mut decoder = build_decoder::<u64>(machine.isa(), machine.version());
+    machine.set_running(true);
+    while machine.running() {
+        if machine.reset_signal() {
+            decoder.reset_instructions_cache();
+        }
+        let pc = *machine.pc();
+        let instruction = decoder.decode(machine.memory_mut(), pc)?;
+        let cycles = machine.cycles();
+        machine.step(&mut decoder)?;
+        on_step(pc, instruction, machine.cycles() - cycles, *machine.pc());
+    }
+    Ok(machine.exit_code())
+}
+
+#[cfg(all(test, feature = "asm"))]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+    use crate::mock_chain::MockChain;
+    use crate::rpc_client::RpcClient;
+    use crate::types::CellOutputWithData;
+    use jsonrpsee::types::ErrorObjectOwned;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/vm")
+            .join(name)
+    }
+
+    /// A call of the fixture code with the default limits and nothing recorded.
+    fn call(vm_version: VmVersion, backend: VmBackend) -> PreparedCall {
+        let chain = MockChain::load(&fixture("chain.json")).unwrap();
+        PreparedCall {
+            resolver: Resolver::new(RpcClient::mock(chain)),
+            args: vec![],
+            script: None,
+            cell: None,
+            cell_out_point: None,
+            cell_live: None,
+            tx: None,
+            vm_version,
+            backend,
+            trace_limit: None,
+            profile: false,
+            gdb: None,
+            coverage: None,
+            limits: Limits {
+                code_size: 1 << 20,
+                memory: ckb_vm::RISCV_MAX_MEMORY,
+                content_size: 1 << 20,
+                debug_size: 1 << 16,
+            },
+        }
+    }
+
+    fn run(binary: &str, call: PreparedCall) -> Result<Execution, Error> {
+        let code = std::fs::read(fixture(binary)).unwrap();
+        execute_riscv_binary(code.into(), call)
+    }
+
+    /// The JSON-RPC error code of a failed execution.
+    fn error_code(result: Result<Execution, Error>) -> i32 {
+        match result {
+            Ok(_) => panic!("execution succeeded"),
+            Err(err) => ErrorObjectOwned::from(err).code(),
+        }
+    }
+
+    fn execute(
+        binary: &str,
+        vm_version: VmVersion,
+        backend: VmBackend,
+    ) -> Result<(Option<Bytes>, u64), String> {
+        run(binary, call(vm_version, backend))
+            .map(|execution| (execution.content, execution.cycles))
+            .map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn backends_agree() {
+        for binary in ["calls", "exit", "echo"] {
+            for vm_version in [VmVersion::V0, VmVersion::V1, VmVersion::V2] {
+                let asm = execute(binary, vm_version, VmBackend::Asm);
+                let interpreter = execute(binary, vm_version, VmBackend::Interpreter);
+                assert_eq!(asm, interpreter, "{binary} on {vm_version:?}");
+            }
+        }
+
+        assert!(execute("calls", VmVersion::V2, VmBackend::Interpreter).is_ok());
+        let (content, cycles) = execute("echo", VmVersion::V2, VmBackend::Interpreter).unwrap();
+        assert_eq!(content.as_deref(), Some(&b"hello"[..]));
+        assert!(cycles > 0);
+        assert_eq!(
+            execute("exit", VmVersion::V2, VmBackend::Interpreter),
+            Err("Script returns 7".to_string())
+        );
+    }
+
+    #[test]
+    fn limits_fail_with_their_codes() {
+        let limited = |binary, limit: fn(&mut Limits)| {
+            let mut call = call(VmVersion::V2, VmBackend::Asm);
+            limit(&mut call.limits);
+            run(binary, call)
+        };
+
+        assert_eq!(error_code(limited("echo", |v| v.code_size = 1000)), 1006);
+        assert!(limited("echo", |v| v.content_size = 5).is_ok());
+        assert_eq!(error_code(limited("echo", |v| v.content_size = 4)), 1008);
+        // prints 5 bytes twice
+        assert!(limited("debug", |v| v.debug_size = 10).is_ok());
+        assert_eq!(error_code(limited("debug", |v| v.debug_size = 9)), 1009);
+    }
+
+    #[test]
+    fn memory_limit_bounds_loads_and_syscalls() {
+        for backend in [VmBackend::Asm, VmBackend::Interpreter] {
+            for binary in ["far_load", "far_content"] {
+                assert!(
+                    run(binary, call(VmVersion::V2, backend)).is_ok(),
+                    "{binary} on {backend:?}"
+                );
+                let mut call = call(VmVersion::V2, backend);
+                call.limits.memory = 1 << 18;
+                assert_eq!(
+                    error_code(run(binary, call)),
+                    1007,
+                    "{binary} on {backend:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn partial_loads_report_the_length_left() {
+        let mut call = call(VmVersion::V2, VmBackend::Asm);
+        call.cell = Some(CellOutputWithData {
+            cell_output: ckb_types::packed::CellOutput::default().into(),
+            hex_data: Some(Hex {
+                hex: b"hello".to_vec(),
+            }),
+        });
+        call.trace_limit = Some(10);
+        let execution = run("partial", call).unwrap();
+        let content = execution.content.unwrap();
+
+        // "ello" is left from offset 1, of which the buffer takes 2 bytes
+        assert_eq!(content[..8], 4u64.to_le_bytes());
+        assert_eq!(content[8..16], *b"el\0\0\0\0\0\0");
+        // nothing is left past the end
+        assert_eq!(content[16..], [0; 16]);
+
+        let trace = execution.trace.unwrap();
+        let loads: Vec<_> = trace.entries[..2]
+            .iter()
+            .map(|entry| (entry.len, entry.bytes.clone().unwrap().hex))
+            .collect();
+        assert_eq!(loads, [(Some(4), b"el".to_vec()), (Some(0), vec![])]);
+    }
+
+    #[test]
+    fn profile_folds_call_stacks() {
+        let mut call = call(VmVersion::V2, VmBackend::Interpreter);
+        call.profile = true;
+        let execution = run("calls", call).unwrap();
+
+        let stacks: HashMap<String, u64> = execution
+            .profile
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let (stack, cycles) = line.rsplit_once(' ').unwrap();
+                (stack.to_string(), cycles.parse().unwrap())
+            })
+            .collect();
+        let mut names: Vec<_> = stacks.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            ["_start", "_start;leaf", "_start;outer", "_start;outer;leaf"]
+        );
+        // outer calls leaf twice on each of its 3 calls
+        assert_eq!(stacks["_start;outer;leaf"], 6 * stacks["_start;leaf"]);
+        assert_eq!(stacks.values().sum::<u64>(), execution.cycles);
+    }
+
+    /// The `DA` hits, `LF` and `LH` of each record of an lcov tracefile, by test name.
+    fn lcov_records(lcov: &str) -> Vec<(String, HashMap<u32, u64>, usize, usize)> {
+        let mut records = vec![];
+        let mut test = String::new();
+        let (mut hits, mut found) = (HashMap::new(), 0);
+        for line in lcov.lines() {
+            let (key, value) = line.split_once(':').unwrap_or((line, ""));
+            match key {
+                "TN" => test = value.to_string(),
+                "DA" => {
+                    let (line, count) = value.split_once(',').unwrap();
+                    hits.insert(line.parse().unwrap(), count.parse().unwrap());
+                }
+                "LF" => found = value.parse().unwrap(),
+                "LH" => {
+                    let hits = std::mem::take(&mut hits);
+                    records.push((test.clone(), hits, found, value.parse().unwrap()));
+                }
+                _ => {}
+            }
+        }
+        records
+    }
+
+    #[test]
+    fn coverage_counts_lines_per_code() {
+        let session = Arc::new(Mutex::new(Coverage::default()));
+        let covered = |binary| {
+            let mut call = call(VmVersion::V2, VmBackend::Interpreter);
+            call.coverage = Some(session.clone());
+            run(binary, call)
+                .ok()
+                .and_then(|execution| execution.coverage)
+        };
+
+        let records = lcov_records(&covered("calls").unwrap());
+        let [(_, hits, found, hit)] = &records[..] else {
+            panic!("one record expected, got {records:?}");
+        };
+        // `call outer` 3 times, `li t0, 10` once per leaf call, its loop 10 times per call
+        assert_eq!((hits[&9], hits[&33], hits[&35]), (3, 7, 70));
+        assert_eq!((*found, *hit), (19, 19));
+
+        covered("calls");
+        // fails with 7, which still counts its coverage
+        covered("exit");
+        let records = lcov_records(&session.lock().unwrap().lcov());
+        assert_eq!(records.len(), 2);
+        let code_hash = |binary| {
+            let code = std::fs::read(fixture(binary)).unwrap();
+            format!("0x{}", encode(blake2b_256(code)))
+        };
+        let calls = records.iter().find(|v| v.0 == code_hash("calls")).unwrap();
+        assert_eq!((calls.1[&35], calls.2, calls.3), (140, 19, 19));
+        // `exit` leaves `crash` unexecuted
+        let exit = records.iter().find(|v| v.0 == code_hash("exit")).unwrap();
+        assert_eq!(exit.1[&24], 0);
+        assert!(exit.3 < exit.2);
+    }
 }
@@ -0,0 +1,163 @@
+use std::borrow::Cow;
+
+use addr2line::gimli::{EndianRcSlice, RunTimeEndian};
+use addr2line::object::{self, Object, ObjectSection, ObjectSymbol, SectionKind, SymbolKind};
+use ckb_jsonrpc_types::Uint64;
+use serde::{Deserialize, Serialize};
+
+/// A function in the code, with the source location when the code has DWARF info.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Frame {
+    pub function: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
+
+/// Where a script stopped. `frames` starts with the innermost inlined function.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Backtrace {
+    pub pc: Uint64,
+    pub frames: Vec<Frame>,
+}
+
+struct Function {
+    address: u64,
+    size: u64,
+    name: String,
+}
+
+/// Function symbols and DWARF info of an ELF, both empty for stripped code.
+///
+/// Holds reference counted sections, so build it where it's used rather than across awaits.
+pub struct Symbols {
+    /// Sorted by address.
+    functions: Vec<Function>,
+    /// Address ranges of the executable sections.
+    text: Vec<(u64, u64)>,
+    dwarf: Option<addr2line::Context<EndianRcSlice<RunTimeEndian>>>,
+}
+
+impl Symbols {
+    pub fn parse(elf: &[u8]) -> Option<Self> {
+        let file = object::File::parse(elf).ok()?;
+
+        let mut functions: Vec<Function> = file
+            .symbols()
+            .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.address() != 0)
+            .filter_map(|symbol| {
+                Some(Function {
+                    address: symbol.address(),
+                    size: symbol.size(),
+                    name: demangle(symbol.name().ok()?),
+                })
+            })
+            .collect();
+        functions.sort_by_key(|function| function.address);
+
+        let text = file
+            .sections()
+            .filter(|section| section.kind() == SectionKind::Text)
+            .map(|section| (section.address(), section.address() + section.size()))
+            .collect();
+
+        let dwarf = file
+            .section_by_name(".debug_info")
+            .filter(|section| section.size() > 0)
+            .and_then(|_| addr2line::Context::new(&file).ok());
+
+        Some(Self {
+            functions,
+            text,
+            dwarf,
+        })
+    }
+
+    /// The function symbol containing `pc`. Symbols without a size extend to the next one.
+    pub fn function(&self, pc: u64) -> Option<&str> {
+        let index = self
+            .functions
+            .partition_point(|function| function.address <= pc)
+            .checked_sub(1)?;
+        let function = &self.functions[index];
+        let end = match function.size {
+            0 => self
+                .functions
+                .get(index + 1)
+                .map_or(u64::MAX, |v| v.address),
+            size => function.address + size,
+        };
+        (pc < end).then_some(function.name.as_str())
+    }
+
+    /// The function symbol starting at `address`, i.e. the callee when jumping there.
+    pub fn function_at(&self, address: u64) -> Option<&str> {
+        self.functions
+            .binary_search_by_key(&address, |function| function.address)
+            .ok()
+            .map(|index| self.functions[index].name.as_str())
+    }
+
+    /// The inlined functions and source lines at `pc` from DWARF, or the function symbol
+    /// containing it.
+    pub fn frames(&self, pc: u64) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        if let Some(dwarf) = &self.dwarf {
+            if let Ok(mut iter) = dwarf.find_frames(pc).skip_all_loads() {
+                while let Ok(Some(frame)) = iter.next() {
+                    frames.push(Frame {
+                        function: frame
+                            .function
+                            .and_then(|function| function.demangle().ok().map(Cow::into_owned)),
+                        file: frame
+                            .location
+                            .as_ref()
+                            .and_then(|location| location.file.map(str::to_string)),
+                        line: frame.location.and_then(|location| location.line),
+                    });
+                }
+            }
+        }
+
+        if frames.first().is_none_or(|frame| frame.function.is_none()) {
+            if let Some(function) = self.function(pc) {
+                let function = Some(function.to_string());
+                match frames.first_mut() {
+                    Some(frame) => frame.function = function,
+                    None => frames.push(Frame {
+                        function,
+                        file: None,
+                        line: None,
+                    }),
+                }
+            }
+        }
+        frames
+    }
+
+    /// The source line of every instruction from DWARF, as the address range of the
+    /// instructions with their file and line. Empty without DWARF info.
+    pub fn lines(&self) -> Vec<(u64, u64, &str, u32)> {
+        let Some(dwarf) = &self.dwarf else {
+            return Vec::new();
+        };
+        self.text
+            .iter()
+            .filter_map(|&(start, end)| dwarf.find_location_range(start, end).ok())
+            .flatten()
+            .filter_map(|(address, len, location)| {
+                Some((address, len, location.file?, location.line?))
+            })
+            .collect()
+    }
+
+    pub fn backtrace(&self, pc: u64) -> Backtrace {
+        Backtrace {
+            pc: pc.into(),
+            frames: self.frames(pc),
+        }
+    }
+}
+
+fn demangle(name: &str) -> String {
+    addr2line::demangle_auto(Cow::Borrowed(name), None).into_owned()
+}
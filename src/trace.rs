@@ -0,0 +1,58 @@
+use ckb_jsonrpc_types::{OutPoint, Script};
+use serde::{Deserialize, Serialize};
+
+use crate::types::Hex;
+
+/// Bytes kept per trace entry of what a syscall returned.
+pub const TRACE_BYTES_LIMIT: usize = 256;
+
+/// One syscall handled during an execution.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TraceEntry {
+    pub syscall: u64,
+    pub name: String,
+    pub source: Option<String>,
+    pub index: Option<u64>,
+    pub field: Option<String>,
+    pub out_point: Option<OutPoint>,
+    pub script: Option<Script>,
+    /// Bytes written to the VM, or read from it by `set_content` and `debug`, up to
+    /// [`TRACE_BYTES_LIMIT`].
+    pub bytes: Option<Hex>,
+    /// The length the syscall reported: the data left from its offset, before partial loading
+    /// and truncation.
+    pub len: Option<u64>,
+    /// `A0` after the syscall, e.g. 1 for an index out of bound.
+    pub return_code: Option<u64>,
+    pub error: Option<String>,
+    /// Cycles consumed so far, including the syscall.
+    pub cycles: u64,
+}
+
+/// The syscalls of an execution, up to a configured number of entries.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Trace {
+    pub entries: Vec<TraceEntry>,
+    /// Syscalls were left out after reaching the limit.
+    pub truncated: bool,
+    #[serde(skip)]
+    limit: usize,
+}
+
+impl Trace {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            entries: Vec::new(),
+            truncated: false,
+            limit,
+        }
+    }
+
+    pub fn push(&mut self, entry: TraceEntry) {
+        if self.entries.len() < self.limit {
+            self.entries.push(entry);
+        } else {
+            self.truncated = true;
+        }
+    }
+}
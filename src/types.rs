@@ -1,15 +1,28 @@
 use core::marker::PhantomData;
 use std::fmt::Debug;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
-use ckb_jsonrpc_types::CellOutput;
+use ckb_jsonrpc_types::{
+    BlockNumber, CellOutput, OutPoint, Script, ScriptHashType, TransactionView, Uint64,
+};
+use ckb_mock_tx_types::ReprMockTransaction;
+use ckb_types::{packed, H256};
 use ckb_vm::Bytes;
 use core::fmt;
 use hex::{FromHex, ToHex};
+use jsonrpsee::types::ErrorObjectOwned;
 use serde::{
     de::{Error, Visitor},
     Deserialize, Deserializer, Serialize, Serializer,
 };
 
+use crate::coverage::Coverage;
+use crate::elf::Section;
+use crate::resolver::{ResolvedTransaction, Resolver, ScriptGroup};
+use crate::ssri_vm::{Limits, VmBackend, VmVersion};
+use crate::trace::Trace;
+
 pub fn serialize<S, T>(data: T, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -90,3 +103,250 @@ pub struct CellOutputWithData {
     pub cell_output: CellOutput,
     pub hex_data: Option<Hex>,
 }
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum LockOrAddress {
+    Script(Script),
+    Address(String),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UdtMetadata {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub icon: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CellOverride {
+    pub out_point: OutPoint,
+    #[serde(flatten)]
+    pub cell: CellOutputWithData,
+}
+
+/// Where to find the code cell whose binary is executed.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum CodeLocator {
+    OutPoint(OutPoint),
+    TypeScript {
+        type_script: Script,
+    },
+    CodeHash {
+        code_hash: H256,
+        hash_type: ScriptHashType,
+    },
+}
+
+/// A RISC-V binary supplied by the caller of `run_binary`.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum BinarySource {
+    Elf {
+        elf: Hex,
+    },
+    /// Relative to the configured binary directory.
+    Path {
+        path: PathBuf,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BinaryRequest {
+    pub binary: BinarySource,
+    #[serde(flatten)]
+    pub call: ScriptCall,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ScriptRequest {
+    pub code: CodeLocator,
+    #[serde(flatten)]
+    pub call: ScriptCall,
+}
+
+impl ScriptRequest {
+    pub fn new(code: CodeLocator, call: ScriptCall) -> Self {
+        Self { code, call }
+    }
+}
+
+/// Arguments and context of one execution of the code.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ScriptCall {
+    pub args: Vec<Hex>,
+    pub script: Option<Script>,
+    pub cell: Option<CellOutputWithData>,
+    /// Fetches `cell` from the chain by its out point instead.
+    pub cell_out_point: Option<OutPoint>,
+    pub tx: Option<TransactionView>,
+    /// Fetches `tx` from the chain by its hash instead.
+    pub tx_hash: Option<H256>,
+    /// Overrides the VM version derived from the script's `hash_type`, e.g. for code-level calls.
+    pub vm_version: Option<VmVersion>,
+    /// Cells replacing the on-chain state of their out points, or added to it, before execution.
+    pub overrides: Option<Vec<CellOverride>>,
+    /// Reject contexts whose scripts don't reference the code cell, on by default.
+    pub strict: Option<bool>,
+    /// Lets `find_cell_by_out_point` resolve spent cells, off by default.
+    pub allow_dead_cells: Option<bool>,
+    /// Records the syscalls of the execution, returned as `trace` or in the error data.
+    pub trace: Option<bool>,
+    /// Attributes the cycles of the execution to functions, returned as `profile`.
+    pub profile: Option<bool>,
+    /// Waits for GDB to attach before running, on the address the server was started with.
+    pub debug: Option<bool>,
+    /// Records the source lines executed, returned as `coverage` and added to the session's.
+    pub coverage: Option<bool>,
+}
+
+impl ScriptCall {
+    pub fn new(args: Vec<Hex>) -> Self {
+        Self {
+            args,
+            script: None,
+            cell: None,
+            cell_out_point: None,
+            tx: None,
+            tx_hash: None,
+            vm_version: None,
+            overrides: None,
+            strict: None,
+            allow_dead_cells: None,
+            trace: None,
+            profile: None,
+            debug: None,
+            coverage: None,
+        }
+    }
+
+    /// Scripts in the context that may be the one running the code: the explicit script,
+    /// otherwise the cell's type and lock, otherwise the scripts of the transaction's inputs
+    /// and outputs.
+    pub fn context_scripts(&self, tx: Option<&ResolvedTransaction>) -> Vec<Script> {
+        if let Some(script) = &self.script {
+            return vec![script.clone()];
+        }
+        if let Some(cell) = &self.cell {
+            let output = &cell.cell_output;
+            return output.type_.iter().chain([&output.lock]).cloned().collect();
+        }
+        tx.map(|tx| tx.scripts().into_iter().map(Into::into).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// A call whose context has been fetched from the chain, ready to run.
+pub struct PreparedCall {
+    /// Resolves lookups of the call, with its overrides applied.
+    pub resolver: Resolver,
+    pub args: Vec<Bytes>,
+    /// The script running the code, explicit or found in the context.
+    pub script: Option<packed::Script>,
+    pub cell: Option<CellOutputWithData>,
+    /// Where `cell` was fetched from by `cell_out_point`.
+    pub cell_out_point: Option<OutPoint>,
+    pub cell_live: Option<bool>,
+    pub tx: Option<ResolvedTransaction>,
+    pub vm_version: VmVersion,
+    pub backend: VmBackend,
+    /// Records up to this many syscalls when tracing.
+    pub trace_limit: Option<usize>,
+    pub profile: bool,
+    /// Address to wait for GDB on when debugging.
+    pub gdb: Option<String>,
+    /// Coverage the execution adds to when covering, merged into the session's by the request.
+    pub coverage: Option<Arc<Mutex<Coverage>>>,
+    pub limits: Limits,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ScriptResponse {
+    pub content: Option<Hex>,
+    /// The code cell the locator was resolved to, `None` for `run_binary`.
+    pub out_point: Option<OutPoint>,
+    /// False when the code cell has been spent and its code was loaded from the transaction
+    /// that created it.
+    pub code_live: bool,
+    /// Whether the cell fetched by `cell_out_point` is live.
+    pub cell_live: Option<bool>,
+    /// The inputs and outputs served as `GroupInput` and `GroupOutput` in tx-level calls.
+    pub script_group: Option<ScriptGroup>,
+    /// The chain tip the result is valid for.
+    pub tip: Option<TipSnapshot>,
+    /// Id of the bundle the upstream traffic was recorded to, when recording.
+    pub bundle: Option<String>,
+    /// The syscalls of the execution, when tracing.
+    pub trace: Option<Trace>,
+    /// Folded stacks of the cycles spent, when profiling.
+    pub profile: Option<String>,
+    /// lcov tracefile of the source lines executed, when covering.
+    pub coverage: Option<String>,
+    /// Cycles the execution consumed.
+    pub cycles: Uint64,
+}
+
+/// What `describe_code` found about a code cell.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CodeDescription {
+    pub out_point: OutPoint,
+    /// False when the code cell has been spent and its code was loaded from the transaction
+    /// that created it.
+    pub code_live: bool,
+    pub data_hash: H256,
+    /// Size of the code, in bytes.
+    pub size: Uint64,
+    pub entry_point: Uint64,
+    pub sections: Vec<Section>,
+    /// Whether the ELF has a symbol table, i.e. isn't stripped.
+    pub has_symbols: bool,
+    /// The ISA extensions from the ELF's RISC-V attributes, e.g. `["i", "m", "c"]`, `None` when
+    /// it has none.
+    pub extensions: Option<Vec<String>>,
+}
+
+/// A call exported as a ckb-debugger mock transaction.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MockTxExport {
+    pub mock_tx: ReprMockTransaction,
+    /// The script to run, as given to `ckb-debugger --script`, e.g. `input.0.lock`.
+    pub script: String,
+    pub script_group: ScriptGroup,
+    /// The program arguments as the VM receives them, hex encoded.
+    pub args: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TipSnapshot {
+    pub block_number: BlockNumber,
+    pub block_hash: H256,
+    /// False when the tip changed during execution, by new blocks or a reorganization, even
+    /// after retrying, so the result may mix in state from after the tip. Debugged calls are
+    /// not retried.
+    pub consistent: bool,
+}
+
+/// Several calls sharing one code load and one view of the chain.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BatchRequest {
+    pub code: CodeLocator,
+    pub calls: Vec<ScriptCall>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum BatchResult {
+    Ok(Box<ScriptResponse>),
+    Err { error: ErrorObjectOwned },
+}
+
+impl From<Result<ScriptResponse, ErrorObjectOwned>> for BatchResult {
+    fn from(result: Result<ScriptResponse, ErrorObjectOwned>) -> Self {
+        match result {
+            Ok(response) => BatchResult::Ok(Box::new(response)),
+            Err(error) => BatchResult::Err { error },
+        }
+    }
+}
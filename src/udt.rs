@@ -0,0 +1,30 @@
+use crate::error::Error;
+use crate::types::Hex;
+
+pub const NAME: &str = "UDT.name";
+pub const SYMBOL: &str = "UDT.symbol";
+pub const DECIMALS: &str = "UDT.decimals";
+pub const ICON: &str = "UDT.icon";
+pub const BALANCE: &str = "UDT.balance";
+
+/// Number of holder cells fetched from the indexer per page in `udt_balance`.
+pub const BALANCE_PAGE_SIZE: u32 = 100;
+
+pub fn decode_string(content: Option<Hex>) -> Result<String, Error> {
+    let content = content.ok_or(Error::Encoding("Missing string content"))?;
+    String::from_utf8(content.hex).map_err(|_| Error::Encoding("Invalid UTF-8 string"))
+}
+
+pub fn decode_u8(content: Option<Hex>) -> Result<u8, Error> {
+    match content.as_ref().map(|v| v.hex.as_slice()) {
+        Some([value]) => Ok(*value),
+        _ => Err(Error::Encoding("Invalid u8 content")),
+    }
+}
+
+pub fn decode_u128(content: Option<Hex>) -> Result<u128, Error> {
+    let content = content.ok_or(Error::Encoding("Missing u128 content"))?;
+    let bytes = <[u8; 16]>::try_from(content.hex.as_slice())
+        .map_err(|_| Error::Encoding("Invalid u128 content"))?;
+    Ok(u128::from_le_bytes(bytes))
+}